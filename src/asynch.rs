@@ -0,0 +1,408 @@
+use lego_device::{BlockDevice, BlockSize, DeviceError};
+use log::{debug, error, trace};
+
+use super::cmd::{mmc_switch, send_status, stop_transmission, Command, Response};
+use super::crc::crc16_ccitt;
+use super::dma::{DescMode, IdmacRing};
+use super::err::{CardError, Interrupt, Timeout};
+use super::irq::{Deadline, HostIrq};
+use super::mmc_reg::{MmcPartition, SwitchAccess, EXT_CSD_PARTITION_CONFIG};
+use super::reg::*;
+use super::DwMmcHost;
+
+/// Caller buffer of a block transfer
+enum DataBuf<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl DataBuf<'_> {
+    fn addr(&self) -> usize {
+        match self {
+            DataBuf::Read(buf) => buf.as_ptr() as usize,
+            DataBuf::Write(buf) => buf.as_ptr() as usize,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            DataBuf::Read(buf) => buf.len(),
+            DataBuf::Write(buf) => buf.len(),
+        }
+    }
+}
+
+/// Future based requests, woken from [`HostIrq::handle_irq`].
+///
+/// Without interrupt mode the futures run the blocking path and are ready on first poll.
+/// The requests borrow the host mutably: the controller, its interrupt waker and the
+/// IDMAC descriptor ring serve one request at a time.
+/// Every wait has a deadline, a request whose interrupt is lost fails with a timeout,
+/// see [`crate::HostHooks::wake_at`]. The card busy after a write or an R1b command is
+/// awaited on the busy clear interrupt, controllers before 2.80a lack it and DAT0 is
+/// polled on every turn of the executor instead.
+impl DwMmcHost {
+    /// Send `cmd` and await its response.
+    pub async fn send_cmd_async(&mut self, cmd: Command) -> Result<Response, CardError> {
+        let Some(irq) = self.mmc_opt.irq() else {
+            return self.mmc_opt.send_cmd(cmd);
+        };
+        self.cmd_async(irq, cmd).await
+    }
+
+    /// Async counterpart of [`DwMmcHost::read_blocks`].
+    pub async fn read_blocks_async(
+        &mut self,
+        lba: usize,
+        count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), DeviceError> {
+        trace!("read blocks async, address: {}, count: {}", lba, count);
+        let Some(irq) = self.mmc_opt.irq() else {
+            return self.read_blocks(lba, count, buf);
+        };
+        self.select_partition_async(irq, MmcPartition::User).await?;
+        self.read_part_blocks_async(irq, lba, count, buf).await
+    }
+
+    /// Async counterpart of [`DwMmcHost::write_blocks`].
+    pub async fn write_blocks_async(
+        &mut self,
+        lba: usize,
+        count: usize,
+        data: &[u8],
+    ) -> Result<(), DeviceError> {
+        trace!("write blocks async, address: {}, count: {}", lba, count);
+        let Some(irq) = self.mmc_opt.irq() else {
+            return self.write_blocks(lba, count, data);
+        };
+        self.check_writable()?;
+        self.select_partition_async(irq, MmcPartition::User).await?;
+        let blk_sz = self.block_size() as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if data.len() < count * blk_sz {
+            return Err(CardError::BufferSize.into());
+        }
+        let mode = self.idmac_mode(data.as_ptr() as usize, count * blk_sz);
+        let max_blk = self.max_async_blocks(mode, count);
+        let mut done = 0;
+        while done < count {
+            let blk = max_blk.min(count - done);
+            let chunk = &data[done * blk_sz..(done + blk) * blk_sz];
+            let ret = self
+                .transfer_async(irq, mode, lba + done, blk, DataBuf::Write(chunk))
+                .await;
+            self.finish_transfer_async(irq, blk, ret).await?;
+            done += blk;
+        }
+        if self.config.verify_writes {
+            self.verify_written_async(irq, lba, count, data).await?;
+        }
+        Ok(())
+    }
+
+    /// Async counterpart of [`DwMmcHost::select_partition`]
+    async fn select_partition_async(
+        &self,
+        irq: &'static HostIrq,
+        part: MmcPartition,
+    ) -> Result<(), CardError> {
+        let Some(config) = self.partition_switch(part)? else {
+            return Ok(());
+        };
+        let cmd = mmc_switch(SwitchAccess::WriteByte, EXT_CSD_PARTITION_CONFIG, config);
+        let status = self.cmd_async(irq, cmd).await?.card_status();
+        debug!("{status:?}");
+        // the card holds DAT0 until the switch is applied
+        let millis = match self.ext_csd.partition_switch_time_ms() {
+            0 => CARD_READY_TMOUT,
+            millis => millis,
+        };
+        self.wait_busy_async(irq, millis).await?;
+        let status = self
+            .cmd_async(irq, send_status(self.rca.address()))
+            .await?
+            .card_status();
+        if status.switch_error() {
+            error!("switch partition to {:?} failed", part);
+            return Err(CardError::SwitchFailed);
+        }
+        self.partition.set(part);
+        Ok(())
+    }
+
+    /// Read from the partition currently selected
+    async fn read_part_blocks_async(
+        &self,
+        irq: &'static HostIrq,
+        lba: usize,
+        count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), DeviceError> {
+        let blk_sz = self.block_size() as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if buf.len() < count * blk_sz {
+            return Err(CardError::BufferSize.into());
+        }
+        let mode = self.idmac_mode(buf.as_ptr() as usize, count * blk_sz);
+        let max_blk = self.max_async_blocks(mode, count);
+        let mut done = 0;
+        while done < count {
+            let blk = max_blk.min(count - done);
+            let chunk = &mut buf[done * blk_sz..(done + blk) * blk_sz];
+            let ret = self
+                .transfer_async(irq, mode, lba + done, blk, DataBuf::Read(chunk))
+                .await;
+            self.finish_transfer_async(irq, blk, ret).await?;
+            done += blk;
+        }
+        Ok(())
+    }
+
+    /// Async counterpart of [`DwMmcHost::verify_written`]
+    async fn verify_written_async(
+        &self,
+        irq: &'static HostIrq,
+        lba: usize,
+        count: usize,
+        data: &[u8],
+    ) -> Result<(), DeviceError> {
+        let blk_sz = self.block_size() as usize;
+        let mut buf = [0u8; BlockSize::Lb512 as usize];
+        for (i, block) in data[..count * blk_sz].chunks_exact(blk_sz).enumerate() {
+            self.read_part_blocks_async(irq, lba + i, 1, &mut buf[..blk_sz])
+                .await?;
+            let (written, read) = (crc16_ccitt(block), crc16_ccitt(&buf[..blk_sz]));
+            if written != read {
+                error!(
+                    "block {} crc16 {:#06x}, read back {:#06x}",
+                    lba + i,
+                    written,
+                    read
+                );
+                return Err(CardError::VerifyFailed.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn max_async_blocks(&self, mode: Option<DescMode>, count: usize) -> usize {
+        match mode {
+            Some(_) => IdmacRing::max_len() / self.block_size() as usize,
+            None => count,
+        }
+    }
+
+    async fn transfer_async(
+        &self,
+        irq: &'static HostIrq,
+        mode: Option<DescMode>,
+        lba: usize,
+        blk: usize,
+        mut data: DataBuf<'_>,
+    ) -> Result<(), CardError> {
+        let blk_sz = self.block_size() as u32;
+        let write = matches!(data, DataBuf::Write(_));
+        match mode {
+            Some(mode) => {
+                let desc_base = self.idmac_map(mode, data.addr(), data.len(), write);
+                self.mmc_opt.start_idmac(desc_base, blk as u32, blk_sz);
+            }
+            None => self.mmc_opt.set_transfer_size(blk as u32, blk_sz),
+        }
+        let cmd = self.block_cmd(write, lba, blk);
+        let ret = self.data_phase_async(irq, mode, cmd, &mut data).await;
+        if mode.is_some() {
+            self.mmc_opt.stop_idmac(ret.is_err())?;
+            self.idmac_unmap(data.addr(), data.len(), write);
+        }
+        ret
+    }
+
+    async fn data_phase_async(
+        &self,
+        irq: &'static HostIrq,
+        mode: Option<DescMode>,
+        cmd: Command,
+        data: &mut DataBuf<'_>,
+    ) -> Result<(), CardError> {
+        let status = self.cmd_async(irq, cmd).await?.card_status();
+        debug!("{status:?}");
+        let opt = &self.mmc_opt;
+        if mode.is_some() {
+            let mut dma_done = false;
+            while !opt.idmac_step(&mut dma_done)? {
+                self.wait_irq(irq, CardError::DataTransferTimeout, || {
+                    opt.dma_status() & IDSTS_CLEAR != 0
+                        || opt.int_status() & (InterruptMask::dto.bits() | INTMASK_ERROR) != 0
+                })
+                .await?;
+            }
+        } else {
+            let ret = self.pio_phase_async(irq, data).await;
+            opt.set_fifo_irq(0);
+            ret?;
+        }
+        // the auto stop may complete after the data, it is awaited by the caller
+        opt.clear_int(opt.int_status() & !InterruptMask::acd.bits());
+        Ok(())
+    }
+
+    /// Drain or fill the FIFO each time its watermark interrupt fires
+    async fn pio_phase_async(
+        &self,
+        irq: &'static HostIrq,
+        data: &mut DataBuf<'_>,
+    ) -> Result<(), CardError> {
+        let opt = &self.mmc_opt;
+        let mut offset = 0;
+        match data {
+            DataBuf::Read(buf) => {
+                while !opt.read_data_step(buf, &mut offset)? {
+                    opt.set_fifo_irq(InterruptMask::rxdr.bits());
+                    self.wait_irq(irq, CardError::DataTransferTimeout, || {
+                        opt.int_status()
+                            & ((InterruptMask::rxdr | InterruptMask::dto).bits() | INTMASK_ERROR)
+                            != 0
+                    })
+                    .await?;
+                }
+            }
+            DataBuf::Write(buf) => {
+                while !opt.write_data_step(buf, &mut offset)? {
+                    // once the whole buffer is queued only the end of the data phase matters
+                    let fifo = if offset < buf.len() {
+                        InterruptMask::txdr.bits()
+                    } else {
+                        0
+                    };
+                    opt.set_fifo_irq(fifo);
+                    self.wait_irq(irq, CardError::DataTransferTimeout, || {
+                        opt.int_status() & (fifo | InterruptMask::dto.bits() | INTMASK_ERROR) != 0
+                    })
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn cmd_async(&self, irq: &'static HostIrq, cmd: Command) -> Result<Response, CardError> {
+        if cmd.data_exp() {
+            // awaited here, start_cmd would spin on a card still busy with the last write
+            self.wait_busy_async(irq, BUSY_TMOUT).await?;
+        }
+        self.mmc_opt.start_cmd(&cmd)?;
+        self.wait_irq(irq, Timeout::WaitCmdDone.into(), || {
+            self.mmc_opt.int_status() & InterruptMask::cmd.bits() != 0
+        })
+        .await?;
+        self.mmc_opt.cmd_response(&cmd)
+    }
+
+    /// Async counterpart of `finish_transfer`: the stop command or the auto stop,
+    /// then the card programming, are awaited on `irq`
+    async fn finish_transfer_async(
+        &self,
+        irq: &'static HostIrq,
+        count: usize,
+        ret: Result<(), CardError>,
+    ) -> Result<(), DeviceError> {
+        if let Err(err) = ret {
+            debug!("{err:?}");
+            self.stop_transmission_async(irq).await?;
+            return Err(DeviceError::IoError);
+        }
+        let opt = &self.mmc_opt;
+        if count > 1 && self.auto_stop {
+            let acd = InterruptMask::acd.bits();
+            self.wait_irq(irq, Timeout::WaitCmdDone.into(), || {
+                opt.int_status() & (acd | INTMASK_ERROR) != 0
+            })
+            .await?;
+            let mask = opt.int_status();
+            opt.clear_int(mask);
+            Interrupt::check(mask).map_err(CardError::from)?;
+        } else if count > 1 {
+            self.stop_transmission_async(irq).await?;
+        }
+        // a write keeps the card busy programming
+        self.wait_busy_async(irq, BUSY_TMOUT).await?;
+        Ok(())
+    }
+
+    /// CMD12, sent again while the controller reports a hardware locked error
+    async fn stop_transmission_async(&self, irq: &'static HostIrq) -> Result<(), CardError> {
+        let opt = &self.mmc_opt;
+        let cmd = stop_transmission();
+        loop {
+            opt.start_cmd(&cmd)?;
+            if opt.int_status() & InterruptMask::hle.bits() == 0 {
+                break;
+            }
+        }
+        self.wait_irq(irq, Timeout::WaitCmdDone.into(), || {
+            opt.int_status() & InterruptMask::cmd.bits() != 0
+        })
+        .await?;
+        let status = opt.cmd_response(&cmd)?.card_status();
+        debug!("{status:?}");
+        self.wait_busy_async(irq, BUSY_TMOUT).await
+    }
+
+    /// Await the card releasing DAT0 after a write or an R1b command, for up to `millis`
+    async fn wait_busy_async(&self, irq: &'static HostIrq, millis: usize) -> Result<(), CardError> {
+        let opt = &self.mmc_opt;
+        let deadline = self.deadline(millis);
+        let deadline = if opt.busy_clear_irq() {
+            deadline
+        } else {
+            deadline.polled()
+        };
+        self.wait_deadline(irq, deadline, Timeout::WaitDataLine.into(), || {
+            !opt.data_busy()
+        })
+        .await
+    }
+
+    /// Await `ready` for up to [`IRQ_TMOUT`], failing with `err` then
+    async fn wait_irq<F: FnMut() -> bool + Unpin>(
+        &self,
+        irq: &'static HostIrq,
+        err: CardError,
+        ready: F,
+    ) -> Result<(), CardError> {
+        self.wait_deadline(irq, self.deadline(IRQ_TMOUT), err, ready)
+            .await
+    }
+
+    /// Await `ready`, giving up with `err` once `deadline` passes or with `NoCard` once
+    /// card detect reports the card gone
+    async fn wait_deadline<F: FnMut() -> bool + Unpin>(
+        &self,
+        irq: &'static HostIrq,
+        deadline: Deadline,
+        err: CardError,
+        mut ready: F,
+    ) -> Result<(), CardError> {
+        let done = irq
+            .wait_until(|| ready() || self.card_gone(irq), Some(deadline))
+            .await;
+        if self.card_gone(irq) {
+            return Err(CardError::NoCard);
+        }
+        if !done {
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Deadline `millis` from now, armed on the `wake_at` hook
+    fn deadline(&self, millis: usize) -> Deadline {
+        Deadline::new(self.mmc_opt.count_down(millis), self.config.hooks.wake_at)
+    }
+}
@@ -0,0 +1,96 @@
+use log::{debug, warn};
+
+use super::err::CardError;
+use super::uhs::Timing;
+use super::DwMmcHost;
+
+/// Card clock during identification
+pub const IDENT_CLOCK: u32 = 400_000;
+/// CIU clock assumed until the platform supplies it, matching the
+/// dividers this driver used to hard-code
+pub const CIU_CLOCK_DEFAULT: u32 = 50_000_000;
+/// CLKDIV divider 0 is 8 bits wide
+const CLKDIV_MAX: u32 = 0xFF;
+
+/// Smallest CLKDIV value whose card clock does not exceed `target`.
+/// The card clock is `ciu / (2 * div)`, a divider of 0 passes `ciu` through.
+pub fn clock_div(ciu: u32, target: u32) -> u32 {
+    if target >= ciu {
+        return 0;
+    }
+    ciu.div_ceil(2 * target.max(1)).min(CLKDIV_MAX)
+}
+
+/// Card clock produced by `div`
+pub fn clock_freq(ciu: u32, div: u32) -> u32 {
+    if div == 0 {
+        ciu
+    } else {
+        ciu / (2 * div)
+    }
+}
+
+impl Timing {
+    /// Highest card clock of the timing in Hz
+    pub fn max_clock(&self, sd: bool) -> u32 {
+        match self {
+            Timing::Legacy if sd => 25_000_000,
+            Timing::Legacy => 26_000_000,
+            Timing::HighSpeed | Timing::Ddr50 if sd => 50_000_000,
+            Timing::HighSpeed => 52_000_000,
+            Timing::Ddr50 => 50_000_000,
+            Timing::Sdr50 => 100_000_000,
+            Timing::Sdr104 => 208_000_000,
+            Timing::Hs200 | Timing::Hs400 => 200_000_000,
+        }
+    }
+}
+
+impl DwMmcHost {
+    /// Program CLKDIV for the fastest card clock not above `target` Hz
+    /// nor the board's `max_clock`
+    pub(crate) fn set_card_clock(&mut self, target: u32) -> Result<(), CardError> {
+        let target = target.min(self.config.max_clock);
+        let div = clock_div(self.config.ciu_clock, target);
+        let clock = clock_freq(self.config.ciu_clock, div);
+        if clock > target {
+            warn!("card clock {}Hz above the {}Hz asked for", clock, target);
+        }
+        self.mmc_opt.reset_clock(1, div)?;
+        self.clock = clock;
+        debug!("card clock: {}Hz, div: {}", clock, div);
+        Ok(())
+    }
+
+    /// Card clock of the current timing. Default speed is further bounded by the
+    /// CSD TRAN_SPEED, faster timings by the switch the card accepted.
+    pub(crate) fn transfer_clock(&self) -> u32 {
+        let max = self.timing.max_clock(self.card_type.is_sd());
+        match self.timing {
+            Timing::Legacy => max.min(self.csd.max_transfer_clock()),
+            _ => max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_div_from_50mhz() {
+        assert_eq!(clock_div(50_000_000, IDENT_CLOCK), 63);
+        assert_eq!(clock_freq(50_000_000, 63), 396_825);
+        assert_eq!(clock_div(50_000_000, 25_000_000), 1);
+        assert_eq!(clock_freq(50_000_000, 1), 25_000_000);
+        assert_eq!(clock_div(50_000_000, 50_000_000), 0);
+        assert_eq!(clock_freq(50_000_000, 0), 50_000_000);
+    }
+
+    #[test]
+    fn clock_div_saturates() {
+        // 400MHz down to 100kHz would take a divider of 2000
+        assert_eq!(clock_div(400_000_000, 100_000), CLKDIV_MAX);
+        assert_eq!(clock_div(50_000_000, 0), CLKDIV_MAX);
+    }
+}
@@ -1,20 +1,38 @@
 use crate::reg::CmdMask;
 use core::fmt::Debug;
 
-use super::sd_reg::{CardStatus, Cic, Cid, Csd, Ocr, Rca};
+use super::mmc_reg::SwitchAccess;
+use super::sd_reg::{CardStatus, Cic, Cid, Csd, Ocr, Rca, SwitchGroup};
 
+const SEND_OP_COND: u32 = 1;
 const ALL_SEND_CID: u32 = 2;
 const SEND_RCA: u32 = 3;
 const SWITCH_FUNCTION: u32 = 6;
 const SELECT_CARD: u32 = 7;
 const SEND_IF_COND: u32 = 8;
+const SEND_EXT_CSD: u32 = 8;
 const SEND_CSD: u32 = 9;
+const VOLTAGE_SWITCH: u32 = 11;
 const STOP_TRANSMISSION: u32 = 12;
+const SEND_STATUS: u32 = 13;
+const SET_BLOCKLEN: u32 = 16;
 const READ_SINGLE_BLOCK: u32 = 17;
+const READ_MULTIPLE_BLOCK: u32 = 18;
+const SEND_TUNING_BLOCK: u32 = 19;
+const SEND_TUNING_BLOCK_HS200: u32 = 21;
+const SET_BLOCK_COUNT: u32 = 23;
 const WRITE_SINGLE_BLOCK: u32 = 24;
+const WRITE_MULTIPLE_BLOCK: u32 = 25;
+const ERASE_WR_BLK_START: u32 = 32;
+const ERASE_WR_BLK_END: u32 = 33;
+const ERASE_GROUP_START: u32 = 35;
+const ERASE_GROUP_END: u32 = 36;
+const ERASE: u32 = 38;
 const APP_CMD: u32 = 55;
 const ACMD_SD_SEND_OP_COND: u32 = 41;
 const ACMD_SET_BUS: u32 = 6;
+const ACMD_SD_STATUS: u32 = 13;
+const ACMD_SEND_SCR: u32 = 51;
 #[derive(Clone, Copy, Default)]
 pub struct Command {
     reg_flags: u32,
@@ -185,6 +203,21 @@ pub fn up_clk() -> Command {
     cmd
 }
 
+/// Clock update sent while CMD11 is in progress, so the controller keeps
+/// waiting for the card to finish the voltage switch
+pub fn up_clk_volt_switch() -> Command {
+    let mut cmd = up_clk();
+    cmd.reg_flags |= CmdMask::volt_switch.bits();
+    cmd
+}
+
+/// CMD11: Switch the signaling voltage to 1.8V
+pub fn voltage_switch() -> Command {
+    let mut cmd = Command::no_data_cmd_r48(VOLTAGE_SWITCH, ResponseType::R1, 0);
+    cmd.reg_flags |= CmdMask::volt_switch.bits();
+    cmd
+}
+
 /// CMD12: Stop transmission
 pub fn stop_transmission() -> Command {
     let mut cmd = Command::default();
@@ -199,6 +232,20 @@ pub fn stop_transmission() -> Command {
     cmd
 }
 
+/// CMD1: MMC operation condition
+pub fn send_op_cond(ocr: u32) -> Command {
+    let reg_flags = CmdMask::start_cmd.bits()
+        | CmdMask::use_hold_reg.bits()
+        | CmdMask::wait_prvdata_complete.bits()
+        | CmdMask::response_expect.bits();
+    Command {
+        reg_flags,
+        index: SEND_OP_COND,
+        arg: ocr,
+        resp_ty: ResponseType::R3,
+    }
+}
+
 /// CMD2: Ask any card to send their CID
 pub fn all_send_cid() -> Command {
     let mut cmd = Command::no_data_cmd_r48(ALL_SEND_CID, ResponseType::R2, 0);
@@ -206,9 +253,18 @@ pub fn all_send_cid() -> Command {
     cmd
 }
 
-/// CMD6: switch function
-pub fn switch_function(arg: u32) -> Command {
-    Command::no_data_cmd_r48(SWITCH_FUNCTION, ResponseType::R1, arg)
+/// CMD6: SD switch function, answered with a 64 byte status on the data lines.
+/// Check mode (`set` false) only queries `function` of `group`, the other groups are kept.
+pub fn switch_function(set: bool, group: SwitchGroup, function: u8) -> Command {
+    let shift = (group as u32 - 1) * 4;
+    let arg = u32::from(set) << 31 | (0x00FF_FFFF & !(0xF << shift)) | u32::from(function) << shift;
+    Command::transfer_cmd(SWITCH_FUNCTION, ResponseType::R1, arg, false)
+}
+
+/// CMD6: eMMC SWITCH, writes `value` to EXT_CSD byte `index`
+pub fn mmc_switch(access: SwitchAccess, index: u8, value: u8) -> Command {
+    let arg = (access as u32) << 24 | u32::from(index) << 16 | u32::from(value) << 8;
+    Command::no_data_cmd_r48(SWITCH_FUNCTION, ResponseType::R1b, arg)
 }
 
 /// CMD7: Select or deselect card
@@ -217,6 +273,11 @@ pub fn select_card(rca: u16) -> Command {
     Command::no_data_cmd_r48(SELECT_CARD, ResponseType::R1b, arg)
 }
 
+/// CMD8: eMMC sends its 512 byte EXT_CSD on the data lines
+pub fn send_ext_csd() -> Command {
+    Command::transfer_cmd(SEND_EXT_CSD, ResponseType::R1, 0, false)
+}
+
 /// CMD9: Send CSD
 pub fn send_csd(rca: u16) -> Command {
     let arg = u32::from(rca) << 16;
@@ -225,16 +286,88 @@ pub fn send_csd(rca: u16) -> Command {
     cmd
 }
 
+/// CMD13: Send the card status
+pub fn send_status(rca: u16) -> Command {
+    Command::no_data_cmd_r48(SEND_STATUS, ResponseType::R1, u32::from(rca) << 16)
+}
+
+/// CMD16: Set the block length of standard capacity cards
+pub fn set_block_len(len: u32) -> Command {
+    Command::no_data_cmd_r48(SET_BLOCKLEN, ResponseType::R1, len)
+}
+
 /// CMD17: Read a single block from the card
 pub fn read_single_block(addr: u32) -> Command {
     Command::transfer_cmd(READ_SINGLE_BLOCK, ResponseType::R1, addr, false)
 }
 
+/// CMD18: Read blocks until a STOP_TRANSMISSION is received.
+/// With `auto_stop` set the controller sends CMD12 itself once BYTCNT is reached.
+pub fn read_multiple_block(addr: u32, auto_stop: bool) -> Command {
+    let mut cmd = Command::transfer_cmd(READ_MULTIPLE_BLOCK, ResponseType::R1, addr, false);
+    if auto_stop {
+        cmd.reg_flags |= CmdMask::send_auto_stop.bits();
+    }
+    cmd
+}
+
+/// CMD19: SD tuning block, a 64 byte pattern read on the 4 data lines
+pub fn send_tuning_block() -> Command {
+    Command::transfer_cmd(SEND_TUNING_BLOCK, ResponseType::R1, 0, false)
+}
+
+/// CMD21: eMMC HS200 tuning block, 128 bytes on an 8-bit bus or 64 bytes on a 4-bit bus
+pub fn send_tuning_block_hs200() -> Command {
+    Command::transfer_cmd(SEND_TUNING_BLOCK_HS200, ResponseType::R1, 0, false)
+}
+
+/// CMD23: Number of blocks of the next CMD18/CMD25, which then needs no CMD12.
+/// `reliable` requests a reliable write, as RPMB writes require.
+pub fn set_block_count(count: u16, reliable: bool) -> Command {
+    let arg = u32::from(reliable) << 31 | u32::from(count);
+    Command::no_data_cmd_r48(SET_BLOCK_COUNT, ResponseType::R1, arg)
+}
+
 /// CMD24: Write block
 pub fn write_single_block(addr: u32) -> Command {
     Command::transfer_cmd(WRITE_SINGLE_BLOCK, ResponseType::R1, addr, true)
 }
 
+/// CMD25: Write blocks until a STOP_TRANSMISSION is received.
+/// With `auto_stop` set the controller sends CMD12 itself once BYTCNT is reached.
+pub fn write_multiple_block(addr: u32, auto_stop: bool) -> Command {
+    let mut cmd = Command::transfer_cmd(WRITE_MULTIPLE_BLOCK, ResponseType::R1, addr, true);
+    if auto_stop {
+        cmd.reg_flags |= CmdMask::send_auto_stop.bits();
+    }
+    cmd
+}
+
+/// CMD32: SD first block of the range to erase
+pub fn erase_wr_blk_start(addr: u32) -> Command {
+    Command::no_data_cmd_r48(ERASE_WR_BLK_START, ResponseType::R1, addr)
+}
+
+/// CMD33: SD last block of the range to erase
+pub fn erase_wr_blk_end(addr: u32) -> Command {
+    Command::no_data_cmd_r48(ERASE_WR_BLK_END, ResponseType::R1, addr)
+}
+
+/// CMD35: eMMC first block of the range to erase
+pub fn erase_group_start(addr: u32) -> Command {
+    Command::no_data_cmd_r48(ERASE_GROUP_START, ResponseType::R1, addr)
+}
+
+/// CMD36: eMMC last block of the range to erase
+pub fn erase_group_end(addr: u32) -> Command {
+    Command::no_data_cmd_r48(ERASE_GROUP_END, ResponseType::R1, addr)
+}
+
+/// CMD38: Erase the range set before, `arg` selects erase, discard, trim or FULE
+pub fn erase(arg: u32) -> Command {
+    Command::no_data_cmd_r48(ERASE, ResponseType::R1b, arg)
+}
+
 /// CMD55: App Command. Indicates that next command will be a app command
 pub fn app_cmd(rca: u16) -> Command {
     Command::no_data_cmd_r48(APP_CMD, ResponseType::R1, u32::from(rca) << 16)
@@ -245,6 +378,11 @@ pub fn send_relative_address() -> Command {
     Command::no_data_cmd_r48(SEND_RCA, ResponseType::R6, 0)
 }
 
+/// CMD3: Assign the relative address of an MMC card
+pub fn set_relative_address(rca: u16) -> Command {
+    Command::no_data_cmd_r48(SEND_RCA, ResponseType::R1, u32::from(rca) << 16)
+}
+
 /// CMD8: Sends memory card interface conditions
 pub fn send_if_cond(voltage: u32, checkpattern: u32) -> Command {
     let arg = voltage << 8 | checkpattern;
@@ -255,10 +393,20 @@ pub fn set_bus_width(arg: u32) -> Command {
     Command::no_data_cmd_r48(ACMD_SET_BUS, ResponseType::R1, arg)
 }
 
+/// ACMD13: SD Status, answered with 64 bytes on the data lines
+pub fn sd_status() -> Command {
+    Command::transfer_cmd(ACMD_SD_STATUS, ResponseType::R1, 0, false)
+}
+
+/// ACMD51: SD Configuration Register, answered with 8 bytes on the data lines
+pub fn send_scr() -> Command {
+    Command::transfer_cmd(ACMD_SEND_SCR, ResponseType::R1, 0, false)
+}
+
 /// ACMD41: App Op Command
-pub fn sd_send_op_cond(host_high_capacity_support: bool, sr18: bool) -> Command {
+pub fn sd_send_op_cond(host_high_capacity_support: bool, sr18: bool, window: u32) -> Command {
     let mut cmd = Command::default();
-    let arg = u32::from(host_high_capacity_support) << 30 | u32::from(sr18) << 24 | 1 << 20;
+    let arg = u32::from(host_high_capacity_support) << 30 | u32::from(sr18) << 24 | window;
     cmd.arg = arg;
     cmd.index = ACMD_SD_SEND_OP_COND;
     cmd.resp_ty = ResponseType::R3;
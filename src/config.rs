@@ -0,0 +1,176 @@
+use core::task::Waker;
+
+use bitflags::bitflags;
+
+use super::clock::CIU_CLOCK_DEFAULT;
+use super::dma::{coherent, identity_map, DmaMode};
+use super::sd_reg::{AccessMode, BusWidth};
+use super::uhs::{SignalVoltage, Timing};
+
+bitflags! {
+    /// Voltages the board can supply to the card
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Voltages: u8{
+        /// 3.2-3.4V card supply
+        const v330 = 0b1;
+        /// 2.9-3.1V card supply
+        const v300 = 0b1 << 1;
+        /// 1.8V I/O signaling, for UHS-I and eMMC HS200/HS400
+        const v180 = 0b1 << 2;
+    }
+}
+
+/// Largest DATA_TIMEOUT field of REG_TMOUT, in card clock cycles
+pub const DATA_TIMEOUT_MAX: u32 = 0xFF_FFFF;
+
+impl Voltages {
+    /// OCR voltage window announced in ACMD41 and CMD1. Zero without a 3V supply,
+    /// which would turn ACMD41 into an inquiry the card never leaves busy on.
+    pub fn ocr_window(&self) -> u32 {
+        let mut window = 0;
+        if self.contains(Voltages::v330) {
+            window |= 0b11 << 20;
+        }
+        if self.contains(Voltages::v300) {
+            window |= 0b11 << 17;
+        }
+        window
+    }
+}
+
+/// Platform callbacks, all optional except the address translation, cache and
+/// wait hooks which have working defaults
+#[derive(Clone, Copy)]
+pub struct HostHooks {
+    /// Translate buffer and descriptor addresses for the IDMAC
+    pub virt_to_phys: fn(usize) -> usize,
+    /// Write back the data cache lines of a virtual address range before the IDMAC
+    /// reads it, a no-op by default for coherent platforms
+    pub cache_clean: fn(usize, usize),
+    /// Drop the data cache lines of a virtual address range the IDMAC writes,
+    /// a no-op by default for coherent platforms
+    pub cache_invalidate: fn(usize, usize),
+    /// Called between two polls of the controller by the blocking API in interrupt
+    /// mode, e.g. to yield to the scheduler
+    pub wait: fn(),
+    /// Wake the waker once `get_macros` reaches the given deadline in microseconds,
+    /// so an async request whose interrupt never comes fails in time. Without it a
+    /// waiting future asks to be polled again on every turn of the executor.
+    pub wake_at: Option<fn(usize, &Waker)>,
+    /// Move the card I/O supply, returns false if it could not
+    pub regulator: Option<fn(SignalVoltage) -> bool>,
+    /// Select one of `HostConfig::tuning_phases` sampling points of the receive clock
+    pub sample_phase: Option<fn(u16)>,
+    /// Card detect of a slot whose card detect pin is wired to a GPIO rather than the
+    /// controller, returns true with a card inserted. Used instead of REG_CDETECT.
+    pub card_detect: Option<fn() -> bool>,
+    /// Vendor setup of the controller right after its reset, gets the register base
+    pub init: Option<fn(usize)>,
+    /// Vendor setup once the card runs at its final timing, e.g. drive strength, delay
+    /// lines or the HS400 data strobe. On the way to HS400 it is also called with
+    /// `Hs200` before the tuning.
+    pub timing: Option<fn(Timing)>,
+}
+
+impl HostHooks {
+    pub const fn new() -> Self {
+        Self {
+            virt_to_phys: identity_map,
+            cache_clean: coherent,
+            cache_invalidate: coherent,
+            wait: core::hint::spin_loop,
+            wake_at: None,
+            regulator: None,
+            sample_phase: None,
+            card_detect: None,
+            init: None,
+            timing: None,
+        }
+    }
+}
+
+impl Default for HostHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Board wiring of a host, handed to [`crate::DwMmcHost::with_config`]
+#[derive(Clone, Copy)]
+pub struct HostConfig {
+    /// Frequency in Hz of the CIU clock feeding the card clock divider
+    pub ciu_clock: u32,
+    /// Highest card clock in Hz the board routing allows
+    pub max_clock: u32,
+    /// Widest data bus wired, `Eight` lets an eMMC use 8 data lines
+    pub bus_width: BusWidth,
+    /// Card supplies available, at least one of `v330` and `v300` is required
+    pub voltages: Voltages,
+    /// SD High Speed (SDR25) is routed, otherwise SD cards stay at default speed
+    pub high_speed: bool,
+    /// FIFO depth in words, needed once FIFOTH no longer holds its reset value,
+    /// e.g. after a bootloader reprogrammed it
+    pub fifo_depth: Option<u32>,
+    /// PIO or IDMAC transfers, IDMAC falls back to PIO on controllers without it
+    pub dma_mode: DmaMode,
+    /// Card clock cycles the controller waits for read data before raising DRTO,
+    /// at most 0xFF_FFFF
+    pub data_timeout: u32,
+    /// REG_CDETECT reads 1 with a card inserted
+    pub cd_active_high: bool,
+    /// REG_WRTPRT reads 0 on a write protected card
+    pub wp_active_low: bool,
+    /// Cards come and go, as opposed to a soldered eMMC. A removable slot is only
+    /// enumerated while card detect, REG_CDETECT or the card detect hook, reports a
+    /// card. Off by default, a slot is then enumerated whatever card detect reads, as
+    /// it always was.
+    pub removable: bool,
+    /// Time in milliseconds the card detect input must settle before a change counts
+    pub debounce_ms: u32,
+    /// UHS-I access modes the board is routed for, need `Voltages::v180` and the regulator hook
+    pub uhs_modes: &'static [AccessMode],
+    /// eMMC timings above High Speed the board is routed for, `Hs200` and `Hs400`.
+    /// Both need `Voltages::v180` and the sample phase hook. HS400 also needs an 8-bit
+    /// bus and the timing hook: the driver sets HS400 mode in EMMC_DDR, the hook must
+    /// enable the data strobe input and its delay line, which are vendor registers.
+    /// Without a regulator hook, VCCQ is taken to be fixed at 1.8V.
+    pub mmc_timings: &'static [Timing],
+    /// Read every written block back and compare its CRC16, for debugging flaky boards
+    pub verify_writes: bool,
+    /// Number of sampling points behind the sample phase hook
+    pub tuning_phases: u16,
+    /// Platform callbacks, see [`HostHooks`]
+    pub hooks: HostHooks,
+}
+
+impl HostConfig {
+    /// A 4-bit slot at 3.3V without card detect, polled PIO and the driver's historic
+    /// 50MHz CIU clock
+    pub const fn new() -> Self {
+        Self {
+            ciu_clock: CIU_CLOCK_DEFAULT,
+            max_clock: u32::MAX,
+            bus_width: BusWidth::Four,
+            voltages: Voltages::v330,
+            high_speed: true,
+            fifo_depth: None,
+            dma_mode: DmaMode::Pio,
+            data_timeout: DATA_TIMEOUT_MAX,
+            cd_active_high: false,
+            wp_active_low: false,
+            removable: false,
+            debounce_ms: 25,
+            uhs_modes: &[],
+            mmc_timings: &[],
+            verify_writes: false,
+            tuning_phases: 0,
+            hooks: HostHooks::new(),
+        }
+    }
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,51 @@
+/// CRC7 of the CMD line and of the CID/CSD registers, x^7 + x^3 + 1 starting from zero.
+/// The card sends it in the upper 7 bits of the last byte.
+pub fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        for bit in (0..8).rev() {
+            let feedback = ((byte >> bit) ^ (crc >> 6)) & 1;
+            crc = (crc << 1) & 0x7F;
+            if feedback != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC16-CCITT of the DAT lines, x^16 + x^12 + x^5 + 1 starting from zero.
+/// On a 4-bit bus the card computes one per line, this one covers the whole block.
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc7_of_commands() {
+        // the last byte on the wire is crc << 1 | 1: 0x95 for CMD0, 0x87 for CMD8
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]), 0x4A);
+        assert_eq!(crc7(&[0x48, 0, 0, 0x01, 0xAA]), 0x43);
+        assert_eq!(crc7(&[0x51, 0, 0, 0, 0]), 0x2A);
+    }
+
+    #[test]
+    fn crc16_of_blocks() {
+        assert_eq!(crc16_ccitt(&[0xFF; 512]), 0x7FA1);
+        assert_eq!(crc16_ccitt(&[0; 512]), 0);
+    }
+}
@@ -0,0 +1,142 @@
+use lego_device::{read_reg, write_reg, DeviceError, DeviceStatus};
+use log::{info, warn};
+
+use super::err::CardError;
+use super::info::SdDevInfo;
+use super::irq::HostIrq;
+use super::mmc_reg::{ExtCsd, MmcPartition};
+use super::reg::*;
+use super::sd_reg::CardType;
+use super::uhs::Timing;
+use super::DwMmcHost;
+
+/// DEBNCE holds 24 bits of CIU clock cycles
+const DEBNCE_MAX: u32 = 0xFF_FFFF;
+
+/// Change of the slot reported by [`DwMmcHost::card_event`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CardEvent {
+    /// A card was inserted and enumerated
+    Inserted,
+    /// The card was pulled, the host is back to `DeviceStatus::Uninitialized`
+    Removed,
+}
+
+/// Slot state: card detect through REG_CDETECT, debounced by the controller, or
+/// through the card detect hook, and the write protect switch through REG_WRTPRT
+impl DwMmcHost {
+    /// A card sits in the slot, always true for a non-removable slot
+    pub fn card_present(&self) -> bool {
+        if !self.config.removable {
+            return true;
+        }
+        if let Some(card_detect) = self.config.hooks.card_detect {
+            return card_detect();
+        }
+        // CDETECT bit 0 is the card_detect_n pin, low with a card inserted
+        let level = read_reg::<u32>(self.sdio_base, REG_CDETECT) & 0b1 != 0;
+        level == self.config.cd_active_high
+    }
+
+    /// The write protect switch of the slot is set, always false for a non-removable slot
+    pub fn write_protect_switch(&self) -> bool {
+        if !self.config.removable {
+            return false;
+        }
+        let level = read_reg::<u32>(self.sdio_base, REG_WRTPRT) & 0b1 != 0;
+        level != self.config.wp_active_low
+    }
+
+    /// Writes are refused: the slot switch is set or the CSD protects the whole card
+    pub fn write_protected(&self) -> bool {
+        self.write_protect_switch() || self.csd.perm_write_protect() || self.csd.tmp_write_protect()
+    }
+
+    /// Act on a change of the slot since the last call: tear the host down after a
+    /// removal, enumerate a newly inserted card. Call it from task context, after
+    /// [`DwMmcHost::wait_card_event`] or periodically without interrupt mode.
+    /// A card that failed to enumerate is tried again on every call.
+    pub fn card_event(&mut self) -> Result<Option<CardEvent>, DeviceError> {
+        let pending = self.irq.is_some_and(|irq| irq.take_card_event());
+        let present = self.card_present();
+        // card detect fired with a card in before and after: it was swapped meanwhile
+        let swapped = pending && present && self.present;
+        if present == self.present && !swapped {
+            return Ok(None);
+        }
+        if self.present {
+            info!("card removed");
+            self.remove_card();
+            if !present {
+                return Ok(Some(CardEvent::Removed));
+            }
+        }
+        info!("card inserted");
+        self.init()?;
+        Ok(Some(CardEvent::Inserted))
+    }
+
+    /// Resolves on the next card detect interrupt, at once without interrupt mode
+    pub async fn wait_card_event(&self) {
+        if let Some(irq) = self.irq {
+            irq.wait_until(|| irq.card_event_pending(), None).await;
+        }
+    }
+
+    /// Program the card detect debounce, `config.debounce_ms` of CIU clock cycles
+    pub(crate) fn set_debounce(&self) {
+        let cycles = u64::from(self.config.ciu_clock) * u64::from(self.config.debounce_ms) / 1000;
+        write_reg::<u32>(
+            self.sdio_base,
+            REG_DEBNCE,
+            cycles.min(u64::from(DEBNCE_MAX)) as u32,
+        );
+    }
+
+    /// Refuse writes and erases up front rather than have the card flag `wp_violation`
+    pub(crate) fn check_writable(&self) -> Result<(), CardError> {
+        self.check_card()?;
+        if self.write_protected() {
+            return Err(CardError::WriteProtected);
+        }
+        Ok(())
+    }
+
+    /// Card detect reported the card gone while a request waits on `irq`
+    pub(crate) fn card_gone(&self, irq: &HostIrq) -> bool {
+        irq.card_event_pending() && !self.card_present()
+    }
+
+    /// Fail requests once the card is gone, until a new card is enumerated
+    pub(crate) fn check_card(&self) -> Result<(), CardError> {
+        if self.status == DeviceStatus::Uninitialized {
+            return Err(CardError::NoCard);
+        }
+        Ok(())
+    }
+
+    /// Abandon the transfer the card left behind, cut the card clock and power
+    /// and forget the card
+    pub(crate) fn remove_card(&mut self) {
+        if let Err(err) = self.mmc_opt.stop_idmac(true) {
+            warn!("fifo reset after removal failed: {}", err);
+        }
+        self.mmc_opt.clear_int(InterruptMask::all().bits());
+        if let Err(err) = self.mmc_opt.reset_clock(0, 0) {
+            warn!("card clock stop after removal failed: {}", err);
+        }
+        write_reg::<u32>(self.sdio_base, REG_PWREN, 0);
+        self.reset_signal_voltage();
+        self.leave_hs400();
+        self.clock = 0;
+        self.timing = Timing::Legacy;
+        self.card_type = CardType::Unknown;
+        self.ext_csd = ExtCsd::new();
+        self.scr = None;
+        self.sd_status = None;
+        self.partition.set(MmcPartition::User);
+        self.info = SdDevInfo::new();
+        self.present = false;
+        self.status = DeviceStatus::Uninitialized;
+    }
+}
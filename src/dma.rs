@@ -0,0 +1,143 @@
+use core::{
+    cell::UnsafeCell,
+    mem::size_of,
+    sync::atomic::{fence, Ordering},
+};
+
+use bitflags::bitflags;
+
+use super::config::HostHooks;
+
+/// Number of descriptors owned by the host, bounds the size of a single IDMAC transfer.
+pub const IDMAC_DESC_NUM: usize = 32;
+/// Bytes carried by one descriptor, must fit in the 13 bit BS1 field.
+pub const IDMAC_DESC_BUF_MAX: usize = 4096;
+
+/// Default address translation for hosts that run with physical addressing.
+pub fn identity_map(addr: usize) -> usize {
+    addr
+}
+
+/// Default cache maintenance for DMA coherent platforms.
+pub fn coherent(_addr: usize, _len: usize) {}
+
+/// How transfers are moved between memory and the controller FIFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaMode {
+    /// The CPU drains and fills the FIFO through REG_DATA.
+    Pio,
+    /// The internal DMA controller walks a descriptor list.
+    Idmac(DescMode),
+}
+
+/// Layout of the IDMAC descriptor list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescMode {
+    /// Every descriptor points to the next one through DES3.
+    Chained,
+    /// Descriptors are contiguous, the last one carries the end of ring bit.
+    Ring,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DescFlags: u32{
+        const own = 0b1 << 31;
+        const ces = 0b1 << 30;
+        const er = 0b1 << 5;
+        const ch = 0b1 << 4;
+        const fs = 0b1 << 3;
+        const ld = 0b1 << 2;
+        const dic = 0b1 << 1;
+    }
+}
+
+/// 32-bit address IDMAC descriptor
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct IdmacDesc {
+    des0: u32,
+    des1: u32,
+    des2: u32,
+    des3: u32,
+}
+
+impl IdmacDesc {
+    pub const fn new() -> Self {
+        Self {
+            des0: 0,
+            des1: 0,
+            des2: 0,
+            des3: 0,
+        }
+    }
+}
+
+/// Descriptor table handed to the IDMAC through REG_DBADDR.
+///
+/// The table is rebuilt and DBADDR reprogrammed for every transfer,
+/// so the host may move between transfers but not during one.
+pub struct IdmacRing {
+    descs: UnsafeCell<[IdmacDesc; IDMAC_DESC_NUM]>,
+}
+
+impl IdmacRing {
+    pub const fn new() -> Self {
+        Self {
+            descs: UnsafeCell::new([IdmacDesc::new(); IDMAC_DESC_NUM]),
+        }
+    }
+
+    /// Largest transfer in bytes that fits in the table.
+    pub const fn max_len() -> usize {
+        IDMAC_DESC_NUM * IDMAC_DESC_BUF_MAX
+    }
+
+    /// Describe `len` bytes at physical address `buf_phys` and return the
+    /// physical address of the first descriptor.
+    pub fn prepare(&self, mode: DescMode, buf_phys: usize, len: usize, hooks: &HostHooks) -> usize {
+        let descs = self.descs.get() as *mut IdmacDesc;
+        let base = (hooks.virt_to_phys)(descs as usize);
+        let count = len.div_ceil(IDMAC_DESC_BUF_MAX).min(IDMAC_DESC_NUM);
+        for i in 0..count {
+            let offset = i * IDMAC_DESC_BUF_MAX;
+            let size = IDMAC_DESC_BUF_MAX.min(len - offset);
+            let mut flags = DescFlags::own | DescFlags::dic;
+            let mut des3 = 0;
+            if i == 0 {
+                flags |= DescFlags::fs;
+            }
+            if i == count - 1 {
+                flags |= DescFlags::ld;
+                // only the last descriptor raises TI/RI
+                flags.remove(DescFlags::dic);
+            }
+            match mode {
+                DescMode::Chained => {
+                    flags |= DescFlags::ch;
+                    if i != count - 1 {
+                        des3 = (base + (i + 1) * size_of::<IdmacDesc>()) as u32;
+                    }
+                }
+                DescMode::Ring => {
+                    if i == count - 1 {
+                        flags |= DescFlags::er;
+                    }
+                }
+            }
+            let desc = IdmacDesc {
+                des0: flags.bits(),
+                des1: size as u32,
+                des2: (buf_phys + offset) as u32,
+                des3,
+            };
+            // SAFETY: i < IDMAC_DESC_NUM and the controller does not own the
+            // table until DBADDR is written by the caller.
+            unsafe { descs.add(i).write_volatile(desc) };
+        }
+        // descriptors must be visible before the controller is kicked
+        fence(Ordering::SeqCst);
+        (hooks.cache_clean)(descs as usize, count * size_of::<IdmacDesc>());
+        base
+    }
+}
@@ -0,0 +1,137 @@
+use core::ops::Range;
+
+use lego_device::BlkDevInfo;
+use log::debug;
+
+use super::err::CardError;
+use super::mmc_reg::MmcPartition;
+use super::sd_reg::CardType;
+use super::DwMmcHost;
+
+/// Busy time allowed per erase unit when the card does not specify one
+const ERASE_TIMEOUT_FALLBACK_MS: usize = 250;
+/// Default eMMC erase and trim time per erase group, Ref JESD84-B51 6.6.9
+const MMC_ERASE_TIMEOUT_FALLBACK_MS: usize = 300;
+/// SD discard completes within a write busy time, Ref PLSS_v7_10 4.14.1
+const SD_DISCARD_TIMEOUT_MS: usize = 250;
+
+/// What CMD38 does to the blocks of the range
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EraseKind {
+    /// Physically erase whole erase units, reading back as all 0 or all 1
+    Erase,
+    /// Drop the mapping of the blocks, their content becomes undefined. Trim on
+    /// eMMC before 4.5.
+    Discard,
+    /// SD full user area logical erase, the range must be the whole user area
+    Fule,
+}
+
+/// Erase, discard and FULE of the user area with CMD32/CMD33 (CMD35/CMD36 on eMMC) and CMD38
+impl DwMmcHost {
+    /// Erase the blocks of `range` as `kind` says. Erase shrinks the range to whole
+    /// erase units, discard works on single blocks and FULE refuses any range but the
+    /// whole user area. Returns the range handled, which may be empty.
+    pub fn erase(&self, range: Range<usize>, kind: EraseKind) -> Result<Range<usize>, CardError> {
+        self.check_writable()?;
+        let arg = self.erase_arg(kind)?;
+        if kind == EraseKind::Fule && range != (0..self.info.block_count() as usize) {
+            return Err(CardError::OutOfRange);
+        }
+        let unit = match kind {
+            EraseKind::Erase => self.erase_unit_blocks(),
+            EraseKind::Discard | EraseKind::Fule => 1,
+        };
+        let start = range.start.next_multiple_of(unit);
+        let end = range.end / unit * unit;
+        if start >= end {
+            return Ok(start..start);
+        }
+        let units = (end - start) / unit;
+        let millis = self.erase_timeout_ms(kind, end - start, units);
+        debug!(
+            "{:?} blocks {}..{}, {} units, timeout {}ms",
+            kind, start, end, units, millis
+        );
+        self.select_partition(MmcPartition::User)?;
+        self.mmc_opt.erase(
+            self.rca,
+            self.card_type == CardType::Mmc,
+            self.card_address(start),
+            self.card_address(end - 1),
+            arg,
+            millis,
+        )?;
+        Ok(start..end)
+    }
+
+    /// Erase unit in 512 byte blocks: the SD CSD erase unit, the eMMC erase group
+    pub fn erase_unit_blocks(&self) -> usize {
+        let blocks = if self.card_type != CardType::Mmc {
+            self.csd.erase_size_blocks()
+        } else if self.ext_csd.erase_group_def() {
+            self.ext_csd.hc_erase_group_blocks()
+        } else {
+            self.csd.mmc_erase_group_blocks()
+        };
+        blocks.max(1) as usize
+    }
+
+    /// CMD38 argument of `kind`, if the card supports it
+    fn erase_arg(&self, kind: EraseKind) -> Result<u32, CardError> {
+        let arg = if self.card_type == CardType::Mmc {
+            match kind {
+                EraseKind::Erase => Some(0),
+                EraseKind::Discard if self.ext_csd.discard_support() => Some(3),
+                EraseKind::Discard if self.ext_csd.trim_support() => Some(1),
+                _ => None,
+            }
+        } else {
+            let status = self.sd_status.as_ref();
+            match kind {
+                // class 5 covers CMD32/CMD33/CMD38
+                EraseKind::Erase if self.csd.command_classes() & (1 << 5) != 0 => Some(0),
+                EraseKind::Discard if status.is_some_and(|s| s.discard_support()) => Some(1),
+                EraseKind::Fule if status.is_some_and(|s| s.fule_support()) => Some(2),
+                _ => None,
+            }
+        };
+        arg.ok_or(CardError::EraseUnsupported)
+    }
+
+    /// Busy time allowed for `blocks` blocks making up `units` erase units
+    fn erase_timeout_ms(&self, kind: EraseKind, blocks: usize, units: usize) -> usize {
+        if self.card_type == CardType::Mmc {
+            let per_group = match kind {
+                EraseKind::Erase if self.ext_csd.erase_group_def() => {
+                    self.ext_csd.erase_timeout_ms()
+                }
+                EraseKind::Discard => self.ext_csd.trim_timeout_ms(),
+                _ => 0,
+            };
+            let per_group = if per_group == 0 {
+                MMC_ERASE_TIMEOUT_FALLBACK_MS
+            } else {
+                per_group
+            };
+            // trim and discard time scales with the erase groups touched
+            let groups = blocks.div_ceil(self.erase_unit_blocks());
+            return per_group * groups;
+        }
+        if kind == EraseKind::Discard {
+            return SD_DISCARD_TIMEOUT_MS;
+        }
+        // Ref PLSS_v7_10 4.14: ERASE_TIMEOUT / ERASE_SIZE x AUs + ERASE_OFFSET seconds
+        if let Some(status) = self.sd_status {
+            let au = status.allocation_unit_blocks() as usize;
+            let size = usize::from(status.erase_size());
+            let timeout = usize::from(status.erase_timeout());
+            if au != 0 && size != 0 && timeout != 0 {
+                let aus = blocks.div_ceil(au);
+                return (timeout * aus * 1000).div_ceil(size)
+                    + usize::from(status.erase_offset()) * 1000;
+            }
+        }
+        ERASE_TIMEOUT_FALLBACK_MS * units
+    }
+}
@@ -1,15 +1,28 @@
 use lego_device::DeviceError;
 
-use super::reg::InterruptMask;
+use super::reg::{DmaStatus, InterruptMask};
 use core::fmt::{Debug, Display};
 
 #[derive(Debug, Clone, Copy)]
 pub enum CardError {
     CardInitErr,
     InterruptErr(Interrupt),
+    DmaErr(Dma),
     TimeoutErr(Timeout),
     VoltagePattern,
     DataTransferTimeout,
+    BufferSize,
+    SwitchFailed,
+    RpmbErr(Rpmb),
+    VoltageSwitch,
+    TuningFailed,
+    NoCard,
+    WriteProtected,
+    EraseUnsupported,
+    EraseFailed,
+    RegisterCrc,
+    VerifyFailed,
+    OutOfRange,
 }
 
 impl Display for CardError {
@@ -18,18 +31,53 @@ impl Display for CardError {
             Self::CardInitErr => write!(f, "Card init failed!"),
             Self::DataTransferTimeout => write!(f, "Data transfer timeout!"),
             Self::InterruptErr(itr) => write!(f, "{}", itr),
+            Self::DmaErr(dma) => write!(f, "{}", dma),
             Self::TimeoutErr(to) => write!(f, "{}", to),
             Self::VoltagePattern => write!(f, "Card voltage pattern failed!"),
+            Self::BufferSize => write!(f, "Buffer does not hold whole requested blocks!"),
+            Self::SwitchFailed => write!(f, "Card refused the switch command!"),
+            Self::RpmbErr(rpmb) => write!(f, "{}", rpmb),
+            Self::VoltageSwitch => write!(f, "Card 1.8V signaling switch failed!"),
+            Self::TuningFailed => write!(f, "No sample phase passed tuning!"),
+            Self::NoCard => write!(f, "No card in the slot!"),
+            Self::WriteProtected => write!(f, "Card is write protected!"),
+            Self::EraseUnsupported => write!(f, "Card does not support this erase!"),
+            Self::EraseFailed => write!(f, "Card erase failed!"),
+            Self::RegisterCrc => write!(f, "Card register crc7 mismatch!"),
+            Self::VerifyFailed => write!(f, "Written data read back differently!"),
+            Self::OutOfRange => write!(f, "Request range not valid for the card or partition!"),
         }
     }
 }
 
+impl CardError {
+    /// The card left the command unanswered, as opposed to answering it badly
+    pub fn is_no_response(&self) -> bool {
+        matches!(
+            self,
+            Self::InterruptErr(Interrupt::ResponseTimeout) | Self::TimeoutErr(Timeout::WaitCmdDone)
+        )
+    }
+}
+
 impl From<Timeout> for CardError {
     fn from(value: Timeout) -> Self {
         Self::TimeoutErr(value)
     }
 }
 
+impl From<Dma> for CardError {
+    fn from(value: Dma) -> Self {
+        Self::DmaErr(value)
+    }
+}
+
+impl From<Rpmb> for CardError {
+    fn from(value: Rpmb) -> Self {
+        Self::RpmbErr(value)
+    }
+}
+
 impl From<Interrupt> for CardError {
     fn from(value: Interrupt) -> Self {
         Self::InterruptErr(value)
@@ -42,6 +90,7 @@ pub enum Timeout {
     WaitCmdLine,
     WaitCmdDone,
     WaitDataLine,
+    WaitCardReady,
     FifoStatus,
 }
 
@@ -52,6 +101,7 @@ impl Display for Timeout {
             Timeout::WaitCmdLine => write!(f, "Card wait command line timeout!"),
             Timeout::WaitCmdDone => write!(f, "Card wait command done timeout!"),
             Timeout::WaitDataLine => write!(f, "Card wait data line timeout!"),
+            Timeout::WaitCardReady => write!(f, "Card wait power up timeout!"),
             Timeout::FifoStatus => write!(f, "Card fifo status exception!"),
         }
     }
@@ -111,14 +161,111 @@ impl Display for Interrupt {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum Dma {
+    FatalBus,
+    DescUnavailable,
+    CardErrorSummary,
+}
+
+impl Dma {
+    pub fn check(status: u32) -> Result<(), Dma> {
+        if status & DmaStatus::fbe.bits() != 0 {
+            return Err(Dma::FatalBus);
+        }
+        if status & DmaStatus::du.bits() != 0 {
+            return Err(Dma::DescUnavailable);
+        }
+        if status & DmaStatus::ces.bits() != 0 {
+            return Err(Dma::CardErrorSummary);
+        }
+        Ok(())
+    }
+}
+
+impl Display for Dma {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Dma::FatalBus => write!(f, "Idmac fatal bus error!"),
+            Dma::DescUnavailable => write!(f, "Idmac descriptor unavailable!"),
+            Dma::CardErrorSummary => write!(f, "Idmac card error summary!"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rpmb {
+    GeneralFailure,
+    AuthFailure,
+    CounterFailure,
+    AddressFailure,
+    WriteFailure,
+    ReadFailure,
+    KeyNotProgrammed,
+    UnknownResult(u16),
+    ResponseType(u16),
+    MacMismatch,
+    NonceMismatch,
+    CounterMismatch,
+}
+
+impl Rpmb {
+    /// Check the result field of a response frame, bit 7 only flags an expired counter
+    pub fn check(result: u16) -> Result<(), Rpmb> {
+        match result & 0x7F {
+            0 => Ok(()),
+            1 => Err(Rpmb::GeneralFailure),
+            2 => Err(Rpmb::AuthFailure),
+            3 => Err(Rpmb::CounterFailure),
+            4 => Err(Rpmb::AddressFailure),
+            5 => Err(Rpmb::WriteFailure),
+            6 => Err(Rpmb::ReadFailure),
+            7 => Err(Rpmb::KeyNotProgrammed),
+            _ => Err(Rpmb::UnknownResult(result)),
+        }
+    }
+}
+
+impl Display for Rpmb {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Rpmb::GeneralFailure => write!(f, "Rpmb general failure!"),
+            Rpmb::AuthFailure => write!(f, "Rpmb authentication failure!"),
+            Rpmb::CounterFailure => write!(f, "Rpmb write counter failure!"),
+            Rpmb::AddressFailure => write!(f, "Rpmb address failure!"),
+            Rpmb::WriteFailure => write!(f, "Rpmb write failure!"),
+            Rpmb::ReadFailure => write!(f, "Rpmb read failure!"),
+            Rpmb::KeyNotProgrammed => write!(f, "Rpmb authentication key not programmed!"),
+            Rpmb::UnknownResult(res) => write!(f, "Rpmb unknown result {:#x}!", res),
+            Rpmb::ResponseType(ty) => write!(f, "Rpmb unexpected response type {:#x}!", ty),
+            Rpmb::MacMismatch => write!(f, "Rpmb response mac mismatch!"),
+            Rpmb::NonceMismatch => write!(f, "Rpmb response nonce mismatch!"),
+            Rpmb::CounterMismatch => write!(f, "Rpmb write counter did not advance!"),
+        }
+    }
+}
+
 impl From<CardError> for DeviceError {
     fn from(value: CardError) -> Self {
         match value {
             CardError::CardInitErr => DeviceError::InvalidConfiguration,
             CardError::InterruptErr(_) => DeviceError::IoError,
+            CardError::DmaErr(_) => DeviceError::IoError,
             CardError::TimeoutErr(_) => DeviceError::Timeout,
             CardError::VoltagePattern => DeviceError::UnsupportedOperation,
             CardError::DataTransferTimeout => DeviceError::IoError,
+            CardError::BufferSize => DeviceError::InvalidConfiguration,
+            CardError::SwitchFailed => DeviceError::UnsupportedOperation,
+            CardError::RpmbErr(_) => DeviceError::IoError,
+            CardError::VoltageSwitch => DeviceError::IoError,
+            CardError::TuningFailed => DeviceError::IoError,
+            CardError::NoCard => DeviceError::IoError,
+            CardError::WriteProtected => DeviceError::UnsupportedOperation,
+            CardError::EraseUnsupported => DeviceError::UnsupportedOperation,
+            CardError::EraseFailed => DeviceError::IoError,
+            CardError::RegisterCrc => DeviceError::IoError,
+            CardError::VerifyFailed => DeviceError::IoError,
+            CardError::OutOfRange => DeviceError::InvalidConfiguration,
         }
     }
 }
@@ -128,3 +275,28 @@ impl From<Timeout> for DeviceError {
         DeviceError::Timeout
     }
 }
+
+#[cfg(feature = "fdt")]
+#[derive(Debug, Clone, Copy)]
+pub enum Fdt {
+    BadMagic,
+    Version,
+    Truncated,
+    TooDeep,
+    BadToken(u32),
+    BadValue,
+}
+
+#[cfg(feature = "fdt")]
+impl Display for Fdt {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Fdt::BadMagic => write!(f, "Fdt bad magic!"),
+            Fdt::Version => write!(f, "Fdt version not supported!"),
+            Fdt::Truncated => write!(f, "Fdt blob truncated!"),
+            Fdt::TooDeep => write!(f, "Fdt nodes nested too deep!"),
+            Fdt::BadToken(token) => write!(f, "Fdt bad structure token {:#x}!", token),
+            Fdt::BadValue => write!(f, "Fdt bad property value!"),
+        }
+    }
+}
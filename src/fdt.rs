@@ -0,0 +1,373 @@
+use log::warn;
+
+use super::config::HostConfig;
+use super::err::Fdt;
+use super::sd_reg::BusWidth;
+use super::DwMmcHost;
+
+// Flattened device tree layout, Ref Devicetree Specification v0.4 Chapter 5
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+/// First version whose header carries `size_dt_struct`, ten words instead of nine
+const FDT_SIZE_STRUCT_VERSION: u32 = 17;
+const FDT_V16_HEADER_SIZE: usize = 36;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Deepest node nesting followed, SoC trees rarely go past 5
+const FDT_MAX_DEPTH: usize = 16;
+
+/// Generic binding of the controller, vendor bindings end in `-dw-mshc`
+const DW_MSHC_COMPATIBLE: &str = "snps,dw-mshc";
+/// Vendor bindings of the same controller that do not follow the naming
+const DW_MSHC_VENDOR_COMPATIBLE: [&str; 1] = ["starfive,jh7110-mmc"];
+
+/// GPIO_ACTIVE_LOW in the flags cell of a GPIO specifier
+const GPIO_ACTIVE_LOW: u32 = 0x1;
+
+/// A flattened device tree blob, only the structure and strings blocks are kept
+#[derive(Clone, Copy)]
+struct FdtBlob<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> FdtBlob<'a> {
+    fn new(blob: &'a [u8]) -> Result<Self, Fdt> {
+        let header = |field: usize| be32(blob, field * 4);
+        if header(0)? != FDT_MAGIC {
+            return Err(Fdt::BadMagic);
+        }
+        let total = header(1)? as usize;
+        let off_structs = header(2)? as usize;
+        let off_strings = header(3)? as usize;
+        let version = header(5)?;
+        if version < FDT_LAST_COMP_VERSION || header(6)? > FDT_LAST_COMP_VERSION {
+            return Err(Fdt::Version);
+        }
+        let size_strings = header(8)? as usize;
+        let blob = blob.get(..total).ok_or(Fdt::Truncated)?;
+        let header_size = if version >= FDT_SIZE_STRUCT_VERSION {
+            FDT_HEADER_SIZE
+        } else {
+            FDT_V16_HEADER_SIZE
+        };
+        if off_structs < header_size || off_strings < header_size {
+            return Err(Fdt::Truncated);
+        }
+        let size_structs = if version >= FDT_SIZE_STRUCT_VERSION {
+            header(9)? as usize
+        } else if off_strings > off_structs {
+            // v16 does not size the structure block, it ends at FDT_END before the strings
+            off_strings - off_structs
+        } else {
+            total.checked_sub(off_structs).ok_or(Fdt::Truncated)?
+        };
+        Ok(Self {
+            structs: slice(blob, off_structs, size_structs)?,
+            strings: slice(blob, off_strings, size_strings)?,
+        })
+    }
+
+    fn token(&self, off: usize) -> Result<u32, Fdt> {
+        be32(self.structs, off)
+    }
+
+    /// NUL terminated node name following a FDT_BEGIN_NODE token at `off`,
+    /// and the offset of the next token
+    fn node_name(&self, off: usize) -> Result<(&'a str, usize), Fdt> {
+        let name = cstr(self.structs.get(off + 4..).ok_or(Fdt::Truncated)?)?;
+        Ok((name, align4(off + 4 + name.len() + 1)))
+    }
+
+    /// Property following a FDT_PROP token at `off`, and the offset of the next token
+    fn prop(&self, off: usize) -> Result<(&'a str, &'a [u8], usize), Fdt> {
+        let len = self.token(off + 4)? as usize;
+        let name_off = self.token(off + 8)? as usize;
+        let name = cstr(self.strings.get(name_off..).ok_or(Fdt::Truncated)?)?;
+        let value = slice(self.structs, off + 12, len)?;
+        Ok((name, value, align4(off + 12 + len)))
+    }
+
+    /// Properties of the node whose first token after the name is at `off`
+    fn props(&self, off: usize) -> Props<'a> {
+        Props { fdt: *self, off }
+    }
+}
+
+/// Properties precede the subnodes of a node, so they end at the first other token
+struct Props<'a> {
+    fdt: FdtBlob<'a>,
+    off: usize,
+}
+
+impl<'a> Iterator for Props<'a> {
+    type Item = Result<(&'a str, &'a [u8]), Fdt>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.off) {
+                Ok(FDT_NOP) => self.off += 4,
+                Ok(FDT_PROP) => {
+                    return Some(self.fdt.prop(self.off).map(|(name, value, next)| {
+                        self.off = next;
+                        (name, value)
+                    }));
+                }
+                Ok(_) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// GPIO wired to the card detect pin instead of the controller's CDETECT input,
+/// assuming `#gpio-cells = <2>` on the GPIO controller. The node's slot is taken as
+/// always occupied until the platform reads the pin through
+/// [`DwMmcHost::set_card_detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CdGpio {
+    /// phandle of the GPIO controller
+    pub controller: u32,
+    pub pin: u32,
+    /// The pin reads 0 with a card inserted
+    pub active_low: bool,
+}
+
+/// A DesignWare MSHC node of the device tree and the board wiring it describes
+#[derive(Clone, Copy)]
+pub struct MshcNode<'a> {
+    /// Node name including the unit address, e.g. `mmc@16010000`
+    pub name: &'a str,
+    /// First `reg` entry, untranslated by the `ranges` of parent buses
+    pub base: usize,
+    pub size: usize,
+    pub config: HostConfig,
+    pub cd_gpio: Option<CdGpio>,
+    /// The slot must not be probed for SDIO, the driver itself never does
+    pub no_sdio: bool,
+}
+
+impl DwMmcHost {
+    /// Host of a controller found with [`mshc_nodes`]
+    pub const fn from_fdt_node(node: &MshcNode, get_macros: fn() -> usize) -> Self {
+        Self::with_config(node.base, get_macros, node.config)
+    }
+}
+
+/// Every enabled `snps,dw-mshc` compatible node of the device tree `blob`
+pub fn mshc_nodes(blob: &[u8]) -> Result<MshcNodes<'_>, Fdt> {
+    let fdt = FdtBlob::new(blob)?;
+    Ok(MshcNodes {
+        fdt,
+        off: 0,
+        depth: 0,
+        cells: [(2, 1); FDT_MAX_DEPTH],
+        done: false,
+    })
+}
+
+/// Walks the structure block once, see [`mshc_nodes`]
+pub struct MshcNodes<'a> {
+    fdt: FdtBlob<'a>,
+    off: usize,
+    depth: usize,
+    /// `#address-cells` and `#size-cells` of each node on the current path
+    cells: [(u32, u32); FDT_MAX_DEPTH],
+    done: bool,
+}
+
+impl<'a> MshcNodes<'a> {
+    /// Read the node whose name starts at `off`, yields it if it is a MSHC
+    fn begin_node(&mut self, off: usize) -> Result<Option<MshcNode<'a>>, Fdt> {
+        let (name, props) = self.fdt.node_name(off)?;
+        self.off = props;
+        if self.depth == FDT_MAX_DEPTH {
+            return Err(Fdt::TooDeep);
+        }
+        // cells of the parent bus describe this node's reg
+        let bus = if self.depth == 0 {
+            (2, 1)
+        } else {
+            self.cells[self.depth - 1]
+        };
+        let mut own = (2, 1);
+        let mut compatible = false;
+        let mut enabled = true;
+        let mut reg = None;
+        for prop in self.fdt.props(props) {
+            let (prop, value) = prop?;
+            match prop {
+                "#address-cells" => own.0 = be32(value, 0)?,
+                "#size-cells" => own.1 = be32(value, 0)?,
+                "compatible" => compatible = is_mshc(value),
+                "status" => enabled = matches!(cstr(value), Ok("okay" | "ok")),
+                "reg" => reg = Some(value),
+                _ => {}
+            }
+        }
+        self.cells[self.depth] = own;
+        self.depth += 1;
+        if !compatible || !enabled {
+            return Ok(None);
+        }
+        // a node the host cannot be placed from is skipped, the next controllers still count
+        let Some((base, size)) = reg.and_then(|reg| {
+            let base = be_cells(reg, 0, bus.0).ok()?;
+            Some((base, be_cells(reg, bus.0 as usize * 4, bus.1).ok()?))
+        }) else {
+            warn!("{}: no usable reg, skipped", name);
+            return Ok(None);
+        };
+        let mut node = MshcNode {
+            name,
+            base: base as usize,
+            size: size as usize,
+            config: HostConfig::new(),
+            cd_gpio: None,
+            no_sdio: false,
+        };
+        // the mmc binding defaults to a removable slot, a single data line and no High Speed
+        node.config.removable = true;
+        node.config.bus_width = BusWidth::One;
+        node.config.high_speed = false;
+        for prop in self.fdt.props(props) {
+            let (prop, value) = prop?;
+            node.apply(prop, value)?;
+        }
+        Ok(Some(node))
+    }
+}
+
+impl MshcNode<'_> {
+    fn apply(&mut self, prop: &str, value: &[u8]) -> Result<(), Fdt> {
+        let config = &mut self.config;
+        match prop {
+            "clock-frequency" => config.ciu_clock = be32(value, 0)?,
+            "max-frequency" => config.max_clock = be32(value, 0)?,
+            "bus-width" => {
+                config.bus_width = match be32(value, 0)? {
+                    1 => BusWidth::One,
+                    4 => BusWidth::Four,
+                    8 => BusWidth::Eight,
+                    _ => return Err(Fdt::BadValue),
+                }
+            }
+            "fifo-depth" => config.fifo_depth = Some(be32(value, 0)?),
+            "cap-sd-highspeed" => config.high_speed = true,
+            // nothing tells a card is missing, the slot counts as occupied
+            "non-removable" | "broken-cd" => config.removable = false,
+            "no-sdio" => self.no_sdio = true,
+            "cd-inverted" => config.cd_active_high = true,
+            "wp-inverted" => config.wp_active_low = true,
+            "cd-gpios" | "cd-gpio" => {
+                let flags = if value.len() >= 12 {
+                    be32(value, 8)?
+                } else {
+                    0
+                };
+                self.cd_gpio = Some(CdGpio {
+                    controller: be32(value, 0)?,
+                    pin: be32(value, 4)?,
+                    active_low: flags & GPIO_ACTIVE_LOW != 0,
+                });
+                config.removable = false;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for MshcNodes<'a> {
+    type Item = Result<MshcNode<'a>, Fdt>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let token = match self.fdt.token(self.off) {
+                Ok(token) => token,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            };
+            let step = match token {
+                FDT_BEGIN_NODE => self.begin_node(self.off).transpose(),
+                FDT_END_NODE => {
+                    self.depth = self.depth.saturating_sub(1);
+                    self.off += 4;
+                    None
+                }
+                FDT_PROP => match self.fdt.prop(self.off) {
+                    Ok((_, _, next)) => {
+                        self.off = next;
+                        None
+                    }
+                    Err(err) => Some(Err(err)),
+                },
+                FDT_NOP => {
+                    self.off += 4;
+                    None
+                }
+                FDT_END => {
+                    self.done = true;
+                    None
+                }
+                token => Some(Err(Fdt::BadToken(token))),
+            };
+            if let Some(step) = step {
+                // a malformed blob cannot be walked any further
+                self.done = step.is_err();
+                return Some(step);
+            }
+        }
+        None
+    }
+}
+
+/// The `compatible` string list names the generic or a vendor binding
+fn is_mshc(value: &[u8]) -> bool {
+    value
+        .split(|b| *b == 0)
+        .filter_map(|s| core::str::from_utf8(s).ok())
+        .any(|s| {
+            s == DW_MSHC_COMPATIBLE
+                || s.ends_with("-dw-mshc")
+                || DW_MSHC_VENDOR_COMPATIBLE.contains(&s)
+        })
+}
+
+fn slice(bytes: &[u8], off: usize, len: usize) -> Result<&[u8], Fdt> {
+    off.checked_add(len)
+        .and_then(|end| bytes.get(off..end))
+        .ok_or(Fdt::Truncated)
+}
+
+fn be32(bytes: &[u8], off: usize) -> Result<u32, Fdt> {
+    let cell = slice(bytes, off, 4)?;
+    Ok(u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+}
+
+/// A number spread over `cells` big endian cells, at most two
+fn be_cells(bytes: &[u8], off: usize, cells: u32) -> Result<u64, Fdt> {
+    match cells {
+        0 => Ok(0),
+        1 => be32(bytes, off).map(u64::from),
+        2 => Ok(u64::from(be32(bytes, off)?) << 32 | u64::from(be32(bytes, off + 4)?)),
+        _ => Err(Fdt::BadValue),
+    }
+}
+
+fn cstr(bytes: &[u8]) -> Result<&str, Fdt> {
+    let end = bytes.iter().position(|b| *b == 0).ok_or(Fdt::Truncated)?;
+    core::str::from_utf8(&bytes[..end]).map_err(|_| Fdt::BadValue)
+}
+
+fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
@@ -0,0 +1,224 @@
+use core::fmt::Debug;
+
+use lego_device::{BlkDevInfo, BlockSize};
+
+use super::sd_reg::{CardType, Cid, Csd, Ocr, Scr, SdStatus};
+use super::DwMmcHost;
+
+/// Block device information of the enumerated card, built from its CID and CSD.
+#[derive(Clone, Copy, Default)]
+pub struct SdDevInfo {
+    cid: Cid,
+    capacity: u64,
+}
+
+impl From<(Cid, Csd)> for SdDevInfo {
+    fn from(value: (Cid, Csd)) -> Self {
+        Self {
+            cid: value.0,
+            capacity: value.1.card_size(),
+        }
+    }
+}
+
+impl SdDevInfo {
+    pub const fn new() -> Self {
+        Self {
+            cid: Cid::new(),
+            capacity: 0,
+        }
+    }
+
+    /// Override the CSD capacity, e.g. with the EXT_CSD sector count of an eMMC
+    pub(crate) fn with_capacity(mut self, capacity: u64) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn manufacturer_id(&self) -> u8 {
+        self.cid.manufacturer_id()
+    }
+
+    pub fn product_revision(&self) -> u8 {
+        self.cid.product_revision()
+    }
+
+    pub fn manufacturing_date(&self) -> (u8, u16) {
+        self.cid.manufacturing_date()
+    }
+}
+
+impl BlkDevInfo for SdDevInfo {
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn block_count(&self) -> u64 {
+        // the host always talks in 512 byte blocks, whatever READ_BL_LEN says
+        self.capacity / BlockSize::Lb512 as u64
+    }
+
+    fn vendor(&self) -> &str {
+        self.cid.oem_id()
+    }
+
+    fn product_name(&self) -> &str {
+        self.cid.product_name()
+    }
+
+    fn serial(&self) -> u32 {
+        self.cid.serial()
+    }
+}
+
+impl Debug for SdDevInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SD Device Info")
+            .field("Vendor", &self.vendor())
+            .field("Product Name", &self.product_name())
+            .field("Serial", &self.serial())
+            .field("Capacity (bytes)", &self.capacity())
+            .field("Block Count", &self.block_count())
+            .finish()
+    }
+}
+
+/// Registers of the enumerated card, taken by [`crate::DwMmcHost::card_info`].
+///
+/// The product accessors read the CID layout of the card's family, the raw
+/// registers stay available through `cid()`, `csd()` and friends.
+#[derive(Clone, Copy)]
+pub struct CardInfo {
+    card_type: CardType,
+    rca: u16,
+    ocr: Ocr,
+    cid: Cid,
+    csd: Csd,
+    scr: Option<Scr>,
+    sd_status: Option<SdStatus>,
+    ext_csd_rev: u8,
+    capacity: u64,
+}
+
+impl From<&DwMmcHost> for CardInfo {
+    fn from(host: &DwMmcHost) -> Self {
+        Self {
+            card_type: host.card_type,
+            rca: host.rca.address(),
+            ocr: host.ocr,
+            cid: host.cid,
+            csd: host.csd,
+            scr: host.scr,
+            sd_status: host.sd_status,
+            ext_csd_rev: host.ext_csd.revision(),
+            capacity: host.info.capacity(),
+        }
+    }
+}
+
+impl CardInfo {
+    pub fn card_type(&self) -> CardType {
+        self.card_type
+    }
+
+    /// Relative card address given during identification
+    pub fn rca(&self) -> u16 {
+        self.rca
+    }
+
+    pub fn ocr(&self) -> Ocr {
+        self.ocr
+    }
+
+    pub fn cid(&self) -> Cid {
+        self.cid
+    }
+
+    pub fn csd(&self) -> Csd {
+        self.csd
+    }
+
+    /// SD Configuration Register, `None` for eMMC
+    pub fn scr(&self) -> Option<Scr> {
+        self.scr
+    }
+
+    /// SD Status, `None` for eMMC or a card that failed ACMD13
+    pub fn sd_status(&self) -> Option<SdStatus> {
+        self.sd_status
+    }
+
+    /// User area size in bytes
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn manufacturer_id(&self) -> u8 {
+        self.cid.manufacturer_id()
+    }
+
+    /// OEM/application ID: the two ASCII characters of [`Cid::oem_id`] on SD, 0 if they
+    /// are not ASCII, one byte on eMMC
+    pub fn oem_id(&self) -> u16 {
+        if self.card_type == CardType::Mmc {
+            u16::from(self.cid.mmc_oem_id())
+        } else {
+            <[u8; 2]>::try_from(self.cid.oem_id().as_bytes()).map_or(0, u16::from_be_bytes)
+        }
+    }
+
+    pub fn product_name(&self) -> &str {
+        if self.card_type == CardType::Mmc {
+            self.cid.mmc_product_name()
+        } else {
+            self.cid.product_name()
+        }
+    }
+
+    /// Product revision as (major, minor)
+    pub fn product_revision(&self) -> (u8, u8) {
+        let prv = if self.card_type == CardType::Mmc {
+            self.cid.mmc_product_revision()
+        } else {
+            self.cid.product_revision()
+        };
+        (prv >> 4, prv & 0xF)
+    }
+
+    pub fn serial(&self) -> u32 {
+        if self.card_type == CardType::Mmc {
+            self.cid.mmc_serial()
+        } else {
+            self.cid.serial()
+        }
+    }
+
+    /// Manufacturing date as (month, year)
+    pub fn manufacturing_date(&self) -> (u8, u16) {
+        if self.card_type == CardType::Mmc {
+            self.cid.mmc_manufacturing_date(self.ext_csd_rev)
+        } else {
+            self.cid.manufacturing_date()
+        }
+    }
+
+    /// SD speed class from the SD Status, 0 if unknown
+    pub fn speed_class(&self) -> u8 {
+        self.sd_status.map_or(0, |status| status.speed_class())
+    }
+}
+
+impl Debug for CardInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Card Info")
+            .field("Card Type", &self.card_type())
+            .field("Manufacturer ID", &self.manufacturer_id())
+            .field("OEM ID", &format_args!("{:#x}", self.oem_id()))
+            .field("Product Name", &self.product_name())
+            .field("Product Revision", &self.product_revision())
+            .field("Serial", &self.serial())
+            .field("Manufacturing Date", &self.manufacturing_date())
+            .field("Capacity (bytes)", &self.capacity())
+            .finish()
+    }
+}
@@ -0,0 +1,218 @@
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use lego_device::{read_reg, write_reg};
+
+use super::reg::*;
+use super::timer::CountDown;
+
+/// Interrupt side of the host.
+///
+/// Lives apart from `DwMmcHost` so the platform interrupt handler can reach it
+/// while a task owns the host, e.g. `static SD_IRQ: HostIrq = HostIrq::new(BASE);`.
+pub struct HostIrq {
+    sdio_base: usize,
+    rintsts: AtomicU32,
+    idsts: AtomicU32,
+    /// Card detect interrupt seen, kept apart from `rintsts` which every command clears
+    card_event: AtomicBool,
+    waker: WakerSlot,
+}
+
+impl HostIrq {
+    pub const fn new(sdio_base: usize) -> Self {
+        Self {
+            sdio_base,
+            rintsts: AtomicU32::new(0),
+            idsts: AtomicU32::new(0),
+            card_event: AtomicBool::new(false),
+            waker: WakerSlot::new(),
+        }
+    }
+
+    /// Entry point for the platform interrupt handler (e.g. from the PLIC claim loop).
+    ///
+    /// Acknowledges the controller, records the status for the waiting request
+    /// and wakes the future awaiting it. A FIFO watermark interrupt is masked until
+    /// the request has serviced the FIFO, acknowledging it alone would not lower it.
+    pub fn handle_irq(&self) {
+        let mask = read_reg::<u32>(self.sdio_base, REG_MINTSTS);
+        if mask != 0 {
+            if mask & INTMASK_FIFO != 0 {
+                let intmask = read_reg::<u32>(self.sdio_base, REG_INTMASK);
+                write_reg::<u32>(self.sdio_base, REG_INTMASK, intmask & !INTMASK_FIFO);
+            }
+            write_reg::<u32>(self.sdio_base, REG_RINTSTS, mask);
+            self.rintsts.fetch_or(mask, Ordering::AcqRel);
+            if mask & InterruptMask::cd.bits() != 0 {
+                self.card_event.store(true, Ordering::Release);
+            }
+        }
+        let idsts = read_reg::<u32>(self.sdio_base, REG_IDSTS) & IDSTS_CLEAR;
+        if idsts != 0 {
+            write_reg::<u32>(self.sdio_base, REG_IDSTS, idsts);
+            self.idsts.fetch_or(idsts, Ordering::AcqRel);
+        }
+        self.waker.wake();
+    }
+
+    /// Resolves to true once `ready` holds, re-checked after every interrupt, or to
+    /// false once `deadline` passes
+    pub(crate) fn wait_until<F: FnMut() -> bool + Unpin>(
+        &self,
+        ready: F,
+        deadline: Option<Deadline>,
+    ) -> IrqEvent<'_, F> {
+        IrqEvent {
+            irq: self,
+            ready,
+            deadline,
+        }
+    }
+
+    /// RINTSTS bits collected since they were last cleared
+    pub(crate) fn rintsts(&self) -> u32 {
+        self.rintsts.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn clear_rintsts(&self, mask: u32) {
+        self.rintsts.fetch_and(!mask, Ordering::AcqRel);
+    }
+
+    /// IDSTS bits collected since they were last cleared
+    pub(crate) fn idsts(&self) -> u32 {
+        self.idsts.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn clear_idsts(&self, mask: u32) {
+        self.idsts.fetch_and(!mask, Ordering::AcqRel);
+    }
+
+    /// A card detect interrupt arrived and was not taken yet
+    pub(crate) fn card_event_pending(&self) -> bool {
+        self.card_event.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn take_card_event(&self) -> bool {
+        self.card_event.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// End of a wait no interrupt may ever complete
+pub(crate) struct Deadline {
+    count_down: CountDown,
+    /// Timer waking the task at the deadline, the task is polled again at once without it
+    wake_at: Option<fn(usize, &Waker)>,
+}
+
+impl Deadline {
+    pub(crate) fn new(count_down: CountDown, wake_at: Option<fn(usize, &Waker)>) -> Self {
+        Self {
+            count_down,
+            wake_at,
+        }
+    }
+
+    /// Poll on every turn of the executor, for a condition no interrupt announces
+    pub(crate) fn polled(self) -> Self {
+        Self {
+            wake_at: None,
+            ..self
+        }
+    }
+}
+
+/// Future returned by [`HostIrq::wait_until`]
+pub struct IrqEvent<'a, F> {
+    irq: &'a HostIrq,
+    ready: F,
+    deadline: Option<Deadline>,
+}
+
+impl<F: FnMut() -> bool + Unpin> Future for IrqEvent<'_, F> {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // register before checking so an interrupt in between is not lost
+        self.irq.waker.register(cx.waker());
+        if (self.ready)() {
+            return Poll::Ready(true);
+        }
+        if let Some(deadline) = &self.deadline {
+            if deadline.count_down.timeout() {
+                return Poll::Ready(false);
+            }
+            match deadline.wake_at {
+                Some(wake_at) => wake_at(deadline.count_down.deadline(), cx.waker()),
+                None => cx.waker().wake_by_ref(),
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Single waker slot shared between a task and the interrupt handler.
+///
+/// The handler never spins on the lock: if the task holds it, the wake is
+/// recorded and replayed by the task once it releases the lock.
+struct WakerSlot {
+    lock: AtomicBool,
+    woken: AtomicBool,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// SAFETY: `waker` is only accessed while `lock` is held
+unsafe impl Sync for WakerSlot {}
+
+impl WakerSlot {
+    const fn new() -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            woken: AtomicBool::new(false),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn register(&self, waker: &Waker) {
+        if !self.try_lock() {
+            // the handler is waking us right now
+            waker.wake_by_ref();
+            return;
+        }
+        // SAFETY: lock held
+        let slot = unsafe { &mut *self.waker.get() };
+        match slot {
+            Some(old) if old.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+        self.lock.store(false, Ordering::Release);
+        if self.woken.swap(false, Ordering::AcqRel) {
+            waker.wake_by_ref();
+        }
+    }
+
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if !self.try_lock() {
+            return;
+        }
+        // SAFETY: lock held
+        let waker = unsafe { (*self.waker.get()).take() };
+        self.lock.store(false, Ordering::Release);
+        if let Some(waker) = waker {
+            self.woken.store(false, Ordering::Release);
+            waker.wake();
+        }
+    }
+}
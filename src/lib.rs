@@ -1,18 +1,62 @@
 #![no_std]
-mod cmd;
+mod asynch;
+mod clock;
+pub mod cmd;
+mod config;
+mod crc;
+mod detect;
+mod dma;
+mod erase;
 pub mod err;
+#[cfg(feature = "fdt")]
+mod fdt;
+mod info;
+mod irq;
+mod mmc;
+mod mmc_reg;
 mod ops;
+mod part;
 mod reg;
+mod rpmb;
 mod sd_reg;
 mod timer;
+mod uhs;
+#[cfg(feature = "vendor-db")]
+mod vendor;
 
+use clock::*;
 use cmd::*;
+pub use config::{HostConfig, HostHooks, Voltages, DATA_TIMEOUT_MAX};
+use core::cell::Cell;
+use core::task::Waker;
+pub use crc::{crc16_ccitt, crc7};
+pub use detect::CardEvent;
+use dma::*;
+pub use dma::{DescMode, DmaMode};
+pub use erase::EraseKind;
+#[cfg(feature = "fdt")]
+pub use fdt::{mshc_nodes, CdGpio, MshcNode, MshcNodes};
+
+use err::CardError;
+pub use info::{CardInfo, SdDevInfo};
+pub use irq::HostIrq;
+pub use mmc_reg::{ExtCsd, MmcPartition};
+pub use part::MmcPartDev;
+pub use rpmb::{MmcRpmb, RpmbFrame, RpmbMac, RPMB_DATA_SIZE, RPMB_FRAME_SIZE};
+pub use sd_reg::{
+    AccessMode, BusWidth, CardType, Cid, Csd, Ocr, SDSpecVersion, Scr, SdStatus, SwitchGroup,
+    SwitchStatus,
+};
+pub use uhs::{SignalVoltage, Timing};
+#[cfg(feature = "vendor-db")]
+pub use vendor::{mmc_manufacturer, sd_manufacturer, CardDescription};
 
 use lego_device::{
     read_reg, write_reg, BlkDevInfo, BlockDevice, BlockSize, Device, DeviceError, DeviceStatus,
     DeviceType,
 };
-use log::{debug, info, trace};
+use log::{debug, error, info, trace, warn};
+use mmc_reg::*;
 use ops::*;
 use reg::*;
 use sd_reg::*;
@@ -25,13 +69,38 @@ pub struct DwMmcHost {
     cic: Cic,
     cid: Cid,
     csd: Csd,
+    card_type: CardType,
+    ext_csd: ExtCsd,
+    scr: Option<Scr>,
+    sd_status: Option<SdStatus>,
+    partition: Cell<MmcPartition>,
+    config: HostConfig,
     hard_config: HardConf,
     mmc_opt: MmcOperate,
+    info: SdDevInfo,
+    auto_stop: bool,
+    idmac: IdmacRing,
+    irq: Option<&'static HostIrq>,
+    signal_voltage: SignalVoltage,
+    timing: Timing,
+    clock: u32,
+    /// Card detect state the host last acted on
+    present: bool,
     status: DeviceStatus,
 }
 
 impl DwMmcHost {
+    /// Host with the default [`HostConfig`]
     pub const fn new(sdio_base: usize, get_macros: fn() -> usize) -> Self {
+        Self::with_config(sdio_base, get_macros, HostConfig::new())
+    }
+
+    /// Host on a board described by `config`
+    pub const fn with_config(
+        sdio_base: usize,
+        get_macros: fn() -> usize,
+        config: HostConfig,
+    ) -> Self {
         let mmc = MmcOperate::new(sdio_base, get_macros);
         Self {
             sdio_base,
@@ -40,121 +109,702 @@ impl DwMmcHost {
             cic: Cic::new(),
             cid: Cid::new(),
             csd: Csd::new(),
+            card_type: CardType::Unknown,
+            ext_csd: ExtCsd::new(),
+            scr: None,
+            sd_status: None,
+            partition: Cell::new(MmcPartition::User),
+            config,
             hard_config: HardConf(0),
             mmc_opt: mmc,
+            info: SdDevInfo::new(),
+            auto_stop: true,
+            idmac: IdmacRing::new(),
+            irq: None,
+            signal_voltage: SignalVoltage::V330,
+            timing: Timing::Legacy,
+            clock: 0,
+            present: false,
             status: DeviceStatus::Uninitialized,
         }
     }
+    /// Reset the controller and enumerate the card in the slot. On failure the host
+    /// is left without a card, the next [`DwMmcHost::card_event`] tries again.
     pub fn init(&mut self) -> Result<(), DeviceError> {
+        let ret = self.init_host();
+        if ret.is_err() {
+            self.present = false;
+            self.status = DeviceStatus::Uninitialized;
+        }
+        ret
+    }
+
+    fn init_host(&mut self) -> Result<(), DeviceError> {
         info!("init dw sdio");
-        let hconf = HardConfig::from_bits(read_reg::<u32>(self.sdio_base, REG_HCON)).unwrap();
+        if self.config.voltages.ocr_window() == 0 {
+            error!("no 3.3V or 3.0V card supply in {:?}", self.config.voltages);
+            return Err(CardError::CardInitErr.into());
+        }
+        // reserved bits 31:28 are kept, they must not fail the decode
+        let hconf = HardConfig::from_bits_retain(read_reg::<u32>(self.sdio_base, REG_HCON));
         debug!("{hconf:?}");
         self.hard_config = HardConf::from(hconf.bits());
+        self.mmc_opt.set_fifo_width(self.hard_config.fifo_width());
+        // HCON does not report the FIFO depth, FIFOTH still holds its reset value
+        // or the bootloader's, read it before programming
+        let fifo_depth = self
+            .config
+            .fifo_depth
+            .unwrap_or(self.mmc_opt.fifoth().rx_wmark() + 1);
+        if self.config.dma_mode != DmaMode::Pio && self.hard_config.dma_interface() != 0 {
+            warn!("controller has no internal dmac, fall back to pio");
+            self.config.dma_mode = DmaMode::Pio;
+        }
         // Reset Control Register
         let reset_mask = ControlMask::controller_reset.bits()
             | ControlMask::fifo_reset.bits()
             | ControlMask::dma_reset.bits();
         write_reg::<u32>(self.sdio_base, REG_CTRL, reset_mask);
         self.mmc_opt.wait_reset(reset_mask)?;
+        if let Some(init) = self.config.hooks.init {
+            init(self.sdio_base);
+        }
+        self.set_debounce();
+        // setup interrupt mask, card detect must be unmasked even with an empty slot
+        write_reg::<u32>(self.sdio_base, REG_RINTSTS, InterruptMask::all().bits());
+        self.mmc_opt.set_irq(self.irq, self.config.hooks.wait);
+        if let Some(irq) = self.irq {
+            irq.clear_rintsts(InterruptMask::all().bits());
+            irq.clear_idsts(IDSTS_CLEAR);
+            let mut intmask = INTMASK_DEFAULT;
+            if self.config.removable {
+                intmask |= InterruptMask::cd.bits();
+            }
+            write_reg::<u32>(self.sdio_base, REG_INTMASK, intmask);
+            let ctrl = read_reg::<u32>(self.sdio_base, REG_CTRL);
+            write_reg::<u32>(
+                self.sdio_base,
+                REG_CTRL,
+                ctrl | ControlMask::int_enable.bits(),
+            );
+            // the slot is sampled below, earlier changes are covered by it
+            irq.take_card_event();
+        } else {
+            write_reg::<u32>(self.sdio_base, REG_INTMASK, 0);
+        }
+        self.present = self.card_present();
+        if !self.present {
+            info!("no card in the slot");
+            self.remove_card();
+            return Err(CardError::NoCard.into());
+        }
+        // cards are identified at 3.3V, a previous init may have left the bus at 1.8V
+        self.reset_signal_voltage();
+        self.leave_hs400();
+        self.timing = Timing::Legacy;
+        write_reg::<u32>(self.sdio_base, REG_UHS, 0);
         // enable power
         write_reg::<u32>(self.sdio_base, REG_PWREN, 1);
-        self.mmc_opt.reset_clock(1, 62)?;
-        write_reg::<u32>(self.sdio_base, REG_TMOUT, 0xFFFFFFFF);
-        // setup interrupt mask
-        write_reg::<u32>(self.sdio_base, REG_RINTSTS, InterruptMask::all().bits());
-        write_reg::<u32>(self.sdio_base, REG_INTMASK, 0);
-        write_reg::<u32>(self.sdio_base, REG_CTYPE, 1);
+        self.set_card_clock(IDENT_CLOCK)?;
+        // response timeout stays at its 0xFF cycles maximum
+        let data_timeout = self.config.data_timeout.min(DATA_TIMEOUT_MAX);
+        write_reg::<u32>(self.sdio_base, REG_TMOUT, data_timeout << 8 | 0xFF);
+        self.mmc_opt.set_fifo_depth(fifo_depth);
+        // identification runs on DAT0 only
+        write_reg::<u32>(self.sdio_base, REG_CTYPE, CTYPE_1BIT);
         write_reg::<u32>(self.sdio_base, REG_IDINTEN, 0);
         write_reg::<u32>(self.sdio_base, REG_BMOD, 1);
 
         // // enumerate card stack
-        self.mmc_opt.send_cmd(idle())?;
-        self.cic = self.mmc_opt.check_version()?;
-        self.ocr = self.mmc_opt.check_v18_sdhc()?;
-        self.cid = self.mmc_opt.check_cid()?;
-        self.rca = self.mmc_opt.check_rca()?;
-        self.csd = self.mmc_opt.check_csd(self.rca)?;
+        self.probe_card()?;
+        // CMD0 puts an eMMC back on the user area
+        self.partition.set(MmcPartition::User);
+        self.scr = None;
+        self.sd_status = None;
+        self.info = SdDevInfo::from((self.cid, self.csd));
         self.mmc_opt.sel_card(self.rca)?;
-        self.mmc_opt.function_switch(16777201)?;
-        self.mmc_opt.set_bus(self.rca)?;
-        self.mmc_opt.reset_clock(1, 1)?;
-        write_reg::<u32>(
-            self.sdio_base,
-            REG_IDINTEN,
-            (DmaIntEn::ri | DmaIntEn::ti).bits(),
-        );
+        if !self.ocr.high_capacity() {
+            // SDSC block length follows READ_BL_LEN until fixed by CMD16
+            self.mmc_opt.set_blk_len(self.block_size() as u32)?;
+        }
+        self.timing = if self.card_type.is_sd() {
+            // every SD card has 4 data lines, a card failing ACMD51 is taken to as well
+            self.scr = self.read_data_reg("scr", |opt, rca| opt.check_scr(rca))?;
+            let four_bit = self.scr.is_none_or(|scr| scr.bus_width_four());
+            if self.config.bus_width != BusWidth::One && four_bit {
+                self.mmc_opt.set_bus(self.rca)?;
+                write_reg::<u32>(self.sdio_base, REG_CTYPE, CTYPE_4BIT);
+            }
+            // only erase timeouts depend on it, a card failing ACMD13 stays usable
+            self.sd_status =
+                self.read_data_reg("sd status", |opt, rca| opt.check_sd_status(rca))?;
+            if self.signal_voltage == SignalVoltage::V180 {
+                self.sd_uhs_timing()?
+            } else if self.config.high_speed && self.sd_high_speed()? {
+                Timing::HighSpeed
+            } else {
+                Timing::Legacy
+            }
+        } else {
+            self.init_mmc()?
+        };
+        self.set_card_clock(self.transfer_clock())?;
+        if let Some(timing) = self.config.hooks.timing {
+            timing(self.timing);
+        }
+        if self.timing.needs_tuning() {
+            self.execute_tuning()?;
+        }
+        // past 100MHz a read may only start once a whole block fits in the FIFO
+        let blk_sz = self.block_size() as u32;
+        match self.timing {
+            Timing::Sdr104 | Timing::Hs200 | Timing::Hs400
+                if self.mmc_opt.fifo_bytes() >= blk_sz =>
+            {
+                self.mmc_opt
+                    .set_card_threshold(blk_sz, self.timing == Timing::Hs400)
+            }
+            _ => self.mmc_opt.set_card_threshold(0, false),
+        }
+        let mut dma_int = DmaIntEn::ri | DmaIntEn::ti;
+        if self.config.dma_mode != DmaMode::Pio {
+            dma_int |= DmaIntEn::fbe | DmaIntEn::du | DmaIntEn::ces | DmaIntEn::ni | DmaIntEn::ai;
+        }
+        write_reg::<u32>(self.sdio_base, REG_IDINTEN, dma_int.bits());
+        #[cfg(feature = "vendor-db")]
+        info!("card: {}", CardInfo::from(&*self).description());
         info!("sdio init success");
         self.status = DeviceStatus::Idle;
         Ok(())
     }
+}
 
-    fn close(&mut self) -> Result<(), DeviceError> {
+impl DwMmcHost {
+    /// Identify the card: CMD8, then ACMD41 with or without HCS, then CMD1 for MMC
+    fn probe_card(&mut self) -> Result<(), CardError> {
+        self.mmc_opt.send_cmd(idle())?;
+        let cic = self.mmc_opt.check_version()?;
+        let window = self.config.voltages.ocr_window();
+        let s18r = cic.is_some()
+            && self.config.hooks.regulator.is_some()
+            && self.config.voltages.contains(Voltages::v180)
+            && self.config.bus_width != BusWidth::One;
+        let sd = match self.mmc_opt.check_v18_sdhc(cic.is_some(), s18r, window) {
+            Ok(ocr) => {
+                self.ocr = ocr;
+                true
+            }
+            Err(err) if err.is_no_response() && cic.is_none() => {
+                debug!("no response to ACMD41, try mmc");
+                self.mmc_opt.send_cmd(idle())?;
+                self.ocr = self.mmc_opt.check_mmc_ocr(window)?;
+                false
+            }
+            Err(err) => return Err(err),
+        };
+        self.cic = cic.unwrap_or_default();
+        if sd && s18r {
+            self.sd_voltage_switch()?;
+        }
+        self.cid = self.mmc_opt.check_cid()?;
+        self.rca = if sd {
+            self.mmc_opt.check_rca()?
+        } else {
+            self.mmc_opt.set_rca(MMC_RCA)?
+        };
+        self.csd = self.mmc_opt.check_csd(self.rca)?;
+        self.card_type = if !sd {
+            CardType::Mmc
+        } else if self.csd.version() == 2 {
+            CardType::Sduc
+        } else if self.ocr.high_capacity() {
+            CardType::SdhcXc
+        } else if cic.is_some() {
+            CardType::SdscV2
+        } else {
+            CardType::SdscV1
+        };
+        info!("card type: {:?}", self.card_type);
+        if self.write_protected() {
+            info!("card is write protected");
+        }
         Ok(())
     }
 
-    fn status(&self) -> DeviceStatus {
-        self.status
+    /// Read an optional register sent on the data lines, `None` if the card fails the
+    /// command. The FIFO is reset then so the next data command does not see its leftovers.
+    fn read_data_reg<T>(
+        &self,
+        name: &str,
+        read: fn(&MmcOperate, Rca) -> Result<T, CardError>,
+    ) -> Result<Option<T>, CardError> {
+        match read(&self.mmc_opt, self.rca) {
+            Ok(reg) => Ok(Some(reg)),
+            Err(err) => {
+                warn!("{} unavailable: {}", name, err);
+                self.mmc_opt.reset_fifo()?;
+                self.mmc_opt.wait_for_data_line()?;
+                Ok(None)
+            }
+        }
     }
 
-    fn reinit(&mut self) -> Result<(), DeviceError> {
-        self.init()
+    /// Switch an SD card to High Speed (SDR25) when it supports it, checking first
+    /// so a card without the function is left at default speed
+    fn sd_high_speed(&mut self) -> Result<bool, CardError> {
+        if !self.csd.supports_switch() {
+            debug!("sd card without cmd6, stay at default speed");
+            return Ok(false);
+        }
+        let hs = AccessMode::Sdr25 as u8;
+        let check = self
+            .mmc_opt
+            .function_switch(false, SwitchGroup::AccessMode, hs)?;
+        if !check.is_supported(SwitchGroup::AccessMode, hs)
+            || !check.switched(SwitchGroup::AccessMode, hs)
+        {
+            return Ok(false);
+        }
+        let set = self
+            .mmc_opt
+            .function_switch(true, SwitchGroup::AccessMode, hs)?;
+        let high_speed = set.switched(SwitchGroup::AccessMode, hs);
+        info!("sd high speed: {}", high_speed);
+        Ok(high_speed)
     }
 
-    fn device_type(&self) -> DeviceType {
-        DeviceType::Block
+    /// eMMC bring-up after CMD7: EXT_CSD, capacity, the widest bus both sides support
+    /// and the fastest timing on it
+    fn init_mmc(&mut self) -> Result<Timing, CardError> {
+        write_reg::<u32>(self.sdio_base, REG_CTYPE, CTYPE_1BIT);
+        if self.csd.mmc_spec_version() < 4 {
+            debug!("mmc before 4.0, no ext_csd");
+            self.info = self
+                .info
+                .with_capacity(self.csd.mmc_block_count() << self.csd.block_length() as u64);
+            return Ok(Timing::Legacy);
+        }
+        self.ext_csd = self.mmc_opt.check_ext_csd()?;
+        if self.ext_csd.sector_count() != 0 {
+            let capacity = u64::from(self.ext_csd.sector_count()) * self.block_size() as u64;
+            self.info = self.info.with_capacity(capacity);
+        }
+        let bus = match self.config.bus_width {
+            BusWidth::Eight => Some((EXT_CSD_BUS_WIDTH_8, CTYPE_8BIT)),
+            BusWidth::Four => Some((EXT_CSD_BUS_WIDTH_4, CTYPE_4BIT)),
+            _ => None,
+        };
+        if let Some((width, ctype)) = bus {
+            self.ext_csd_switch(EXT_CSD_BUS_WIDTH, width)?;
+            write_reg::<u32>(self.sdio_base, REG_CTYPE, ctype);
+            info!("mmc bus width: {:?}", self.config.bus_width);
+        }
+        self.mmc_timing()
     }
 
-    fn error_handle(&self) -> DeviceStatus {
-        DeviceStatus::Error
+    /// Board description used by the next `init`
+    pub fn config(&self) -> &HostConfig {
+        &self.config
     }
-}
 
-impl DwMmcHost {
-    fn block_size(&self) -> BlockSize {
-        BlockSize::Lb512
+    /// Replace the board description, takes effect on the next `init`
+    pub fn set_config(&mut self, config: HostConfig) {
+        self.config = config;
+    }
+
+    /// Widest data bus wired on the board, `Eight` lets an eMMC use 8 data lines.
+    /// Takes effect on the next `init`.
+    pub fn set_max_bus_width(&mut self, width: BusWidth) {
+        self.config.bus_width = width;
+    }
+
+    /// Snapshot of the card registers, `None` until a card is enumerated
+    pub fn card_info(&self) -> Option<CardInfo> {
+        if self.status == DeviceStatus::Uninitialized {
+            return None;
+        }
+        Some(CardInfo::from(self))
+    }
+
+    /// SD Configuration Register read with ACMD51, `None` for eMMC or before `init`
+    pub fn scr(&self) -> Option<Scr> {
+        self.scr
+    }
+
+    /// SD Status read with ACMD13, `None` for eMMC, before `init` or if the card failed it
+    pub fn sd_status(&self) -> Option<SdStatus> {
+        self.sd_status
+    }
+
+    /// Extended CSD of an eMMC, all zero for SD cards
+    pub fn ext_csd(&self) -> &ExtCsd {
+        &self.ext_csd
+    }
+
+    /// Card family detected by the last `init`
+    pub fn card_type(&self) -> CardType {
+        self.card_type
+    }
+
+    /// Choose how multiple block transfers are terminated: by the controller's
+    /// auto stop (default) or by an explicit CMD12 once the data phase is over.
+    pub fn set_auto_stop(&mut self, enable: bool) {
+        self.auto_stop = enable;
+    }
+
+    /// Override the FIFO depth in words when FIFOTH no longer holds its reset value,
+    /// e.g. after a bootloader reprogrammed it. Takes effect on the next `init`.
+    pub fn set_fifo_depth(&mut self, depth: u32) {
+        self.config.fifo_depth = Some(depth);
+    }
+
+    /// Complete the async requests and report card detect from interrupts.
+    ///
+    /// The platform routes the controller interrupt to [`HostIrq::handle_irq`]. The
+    /// blocking API keeps polling the controller and calls `wait` between two polls,
+    /// e.g. to yield to the scheduler; only the `*_async` requests are woken by the
+    /// interrupt itself, see [`DwMmcHost::set_wake_at`] for their timeouts. Takes
+    /// effect on the next `init`.
+    pub fn set_interrupt_mode(&mut self, irq: &'static HostIrq, wait: fn()) {
+        self.irq = Some(irq);
+        self.config.hooks.wait = wait;
+    }
+
+    /// Wake the `*_async` requests at their deadline from a platform timer rather than
+    /// have them polled on every turn of the executor, see [`HostHooks::wake_at`]
+    pub fn set_wake_at(&mut self, wake_at: fn(usize, &Waker)) {
+        self.config.hooks.wake_at = Some(wake_at);
+    }
+
+    /// Card detect through a GPIO: `present` replaces REG_CDETECT and makes the slot
+    /// removable. There is no card detect interrupt then, call
+    /// [`DwMmcHost::card_event`] periodically. Takes effect on the next `init`.
+    pub fn set_card_detect(&mut self, present: fn() -> bool) {
+        self.config.hooks.card_detect = Some(present);
+        self.config.removable = true;
+    }
+
+    /// Allow UHS-I: ACMD41 asks for 1.8V signaling and `regulator` moves the I/O
+    /// supply, returning false if it could not. `modes` are the access modes the
+    /// board is routed for. Takes effect on the next `init`.
+    pub fn set_uhs(&mut self, regulator: fn(SignalVoltage) -> bool, modes: &'static [AccessMode]) {
+        self.config.voltages |= Voltages::v180;
+        self.config.hooks.regulator = Some(regulator);
+        self.config.uhs_modes = modes;
+    }
+
+    /// Sample phase control used by tuning: `sample_phase` selects one of `phases`
+    /// evenly spaced sampling points of the receive clock. Required for SDR104.
+    pub fn set_sample_phase(&mut self, sample_phase: fn(u16), phases: u16) {
+        self.config.hooks.sample_phase = Some(sample_phase);
+        self.config.tuning_phases = phases;
+    }
+
+    /// eMMC timings above High Speed the board is routed for, see [`HostConfig::mmc_timings`].
+    /// Takes effect on the next `init`.
+    pub fn set_mmc_timings(&mut self, timings: &'static [Timing]) {
+        self.config.voltages |= Voltages::v180;
+        self.config.mmc_timings = timings;
+    }
+
+    /// Frequency in Hz of the CIU clock feeding the card clock divider.
+    /// Takes effect on the next `init`.
+    pub fn set_ciu_clock(&mut self, hz: u32) {
+        self.config.ciu_clock = hz;
+    }
+
+    /// Card clock in Hz the divider achieved, zero before `init`
+    pub fn clock(&self) -> u32 {
+        self.clock
+    }
+
+    /// Bus timing selected by the last `init`
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// I/O signaling level of the card bus
+    pub fn signal_voltage(&self) -> SignalVoltage {
+        self.signal_voltage
+    }
+
+    /// Select PIO or IDMAC transfers, takes effect on the next `init`.
+    ///
+    /// `virt_to_phys` translates buffer and descriptor addresses for the IDMAC;
+    /// buffers handed to the IDMAC must be physically contiguous.
+    pub fn set_dma_mode(&mut self, mode: DmaMode, virt_to_phys: fn(usize) -> usize) {
+        self.config.dma_mode = mode;
+        self.config.hooks.virt_to_phys = virt_to_phys;
+    }
+
+    /// Cache maintenance around IDMAC transfers on platforms where DMA is not coherent:
+    /// `clean` writes back and `invalidate` drops the lines of a virtual address range.
+    /// Ranges may start and end mid cache line.
+    pub fn set_dma_cache_ops(&mut self, clean: fn(usize, usize), invalidate: fn(usize, usize)) {
+        self.config.hooks.cache_clean = clean;
+        self.config.hooks.cache_invalidate = invalidate;
+    }
+
+    /// Hardware partition currently reached by data commands
+    pub fn partition(&self) -> MmcPartition {
+        self.partition.get()
+    }
+
+    /// Size in bytes of `part`, zero when the card does not have it
+    pub fn partition_size(&self, part: MmcPartition) -> u64 {
+        match part {
+            MmcPartition::User => self.info.capacity(),
+            _ if self.card_type != CardType::Mmc => 0,
+            _ => self.ext_csd.partition_size(part),
+        }
+    }
+
+    /// Block device handle on one hardware partition of an eMMC.
+    ///
+    /// Handles share this host, the partition is switched before each of their transfers.
+    /// The RPMB partition only takes authenticated frames and has no block handle.
+    pub fn partition_dev(&self, part: MmcPartition) -> Result<MmcPartDev<'_>, DeviceError> {
+        if part == MmcPartition::Rpmb || self.partition_size(part) == 0 {
+            return Err(DeviceError::UnsupportedOperation);
+        }
+        let info = self.info.with_capacity(self.partition_size(part));
+        Ok(MmcPartDev::new(self, part, info))
+    }
+
+    /// Route following data commands to `part` through EXT_CSD PARTITION_CONFIG,
+    /// keeping the boot configuration bits.
+    pub fn select_partition(&self, part: MmcPartition) -> Result<(), CardError> {
+        let Some(config) = self.partition_switch(part)? else {
+            return Ok(());
+        };
+        self.mmc_opt.mmc_switch(
+            self.rca,
+            EXT_CSD_PARTITION_CONFIG,
+            config,
+            self.ext_csd.partition_switch_time_ms(),
+        )?;
+        self.partition.set(part);
+        Ok(())
+    }
+
+    /// PARTITION_CONFIG routing data commands to `part`, `None` when they already go there
+    fn partition_switch(&self, part: MmcPartition) -> Result<Option<u8>, CardError> {
+        self.check_card()?;
+        if self.partition.get() == part {
+            return Ok(None);
+        }
+        if self.card_type != CardType::Mmc {
+            return Err(CardError::SwitchFailed);
+        }
+        debug!("switch partition: {:?} -> {:?}", self.partition.get(), part);
+        Ok(Some((self.ext_csd.partition_config() & !0x7) | part as u8))
     }
-    pub fn read_block(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
-        trace!("read block, address: {},", lba);
-        let cmd = read_single_block(lba as u32);
-        match self.mmc_opt.send_cmd(cmd) {
-            Ok(resp) => {
+
+    /// Read `count` blocks starting at `lba` of the user area, using CMD18 when more
+    /// than one block is requested.
+    pub fn read_blocks(&self, lba: usize, count: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
+        self.select_partition(MmcPartition::User)?;
+        self.read_part_blocks(lba, count, buf)
+    }
+
+    /// Write `count` blocks starting at `lba` of the user area, using CMD25 when more
+    /// than one block is requested.
+    pub fn write_blocks(&self, lba: usize, count: usize, data: &[u8]) -> Result<(), DeviceError> {
+        self.select_partition(MmcPartition::User)?;
+        self.write_part_blocks(lba, count, data)
+    }
+
+    /// Read from the partition currently selected
+    pub(crate) fn read_part_blocks(
+        &self,
+        lba: usize,
+        count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), DeviceError> {
+        trace!("read blocks, address: {}, count: {}", lba, count);
+        let blk_sz = self.block_size() as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if buf.len() < count * blk_sz {
+            return Err(CardError::BufferSize.into());
+        }
+        if let Some(mode) = self.idmac_mode(buf.as_ptr() as usize, count * blk_sz) {
+            return self.idmac_blocks(mode, lba, count, buf.as_mut_ptr() as usize, false);
+        }
+        let cmd = self.block_cmd(false, lba, count);
+        self.mmc_opt.set_transfer_size(count as u32, blk_sz as u32);
+        let ret = self.mmc_opt.send_cmd(cmd).and_then(|resp| {
+            let status = resp.card_status();
+            debug!("{status:?}");
+            self.mmc_opt.read_data(&mut buf[..count * blk_sz])
+        });
+        self.finish_transfer(count, ret)
+    }
+
+    /// Write to the partition currently selected
+    pub(crate) fn write_part_blocks(
+        &self,
+        lba: usize,
+        count: usize,
+        data: &[u8],
+    ) -> Result<(), DeviceError> {
+        trace!("write blocks, address: {}, count: {}", lba, count);
+        self.check_writable()?;
+        let blk_sz = self.block_size() as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if data.len() < count * blk_sz {
+            return Err(CardError::BufferSize.into());
+        }
+        if let Some(mode) = self.idmac_mode(data.as_ptr() as usize, count * blk_sz) {
+            self.idmac_blocks(mode, lba, count, data.as_ptr() as usize, true)?;
+        } else {
+            let cmd = self.block_cmd(true, lba, count);
+            self.mmc_opt.set_transfer_size(count as u32, blk_sz as u32);
+            let ret = self.mmc_opt.send_cmd(cmd).and_then(|resp| {
                 let status = resp.card_status();
                 debug!("{status:?}");
-                let blk_sz = self.block_size() as u32;
-                let blk = buf.len() as u32 / blk_sz;
-                match self.mmc_opt.read_data(buf, blk, blk_sz) {
-                    Ok(_) => Ok(()),
-                    Err(err) => {
-                        debug!("{err:?}");
-                        self.mmc_opt.stop_transmission_ops()?;
-                        Err(DeviceError::IoError)
-                    }
-                }
+                self.mmc_opt.write_data(&data[..count * blk_sz])
+            });
+            self.finish_transfer(count, ret)?;
+        }
+        if self.config.verify_writes {
+            self.verify_written(lba, count, data)?;
+        }
+        Ok(())
+    }
+
+    /// Read back the blocks just written one by one and compare their CRC16
+    pub(crate) fn verify_written(
+        &self,
+        lba: usize,
+        count: usize,
+        data: &[u8],
+    ) -> Result<(), DeviceError> {
+        let blk_sz = self.block_size() as usize;
+        let mut buf = [0u8; BlockSize::Lb512 as usize];
+        for (i, block) in data[..count * blk_sz].chunks_exact(blk_sz).enumerate() {
+            self.read_part_blocks(lba + i, 1, &mut buf[..blk_sz])?;
+            let (written, read) = (crc16_ccitt(block), crc16_ccitt(&buf[..blk_sz]));
+            if written != read {
+                error!(
+                    "block {} crc16 {:#06x}, read back {:#06x}",
+                    lba + i,
+                    written,
+                    read
+                );
+                return Err(CardError::VerifyFailed.into());
             }
-            Err(err) => {
-                debug!("{err:?}");
-                self.mmc_opt.stop_transmission_ops()?;
-                Err(DeviceError::IoError)
+        }
+        Ok(())
+    }
+
+    /// Blocks making up a buffer of `len` bytes, which must hold at least one whole block
+    /// and no partial one
+    pub(crate) fn whole_blocks(&self, len: usize) -> Result<usize, CardError> {
+        let blk_sz = self.block_size() as usize;
+        if len == 0 || !len.is_multiple_of(blk_sz) {
+            return Err(CardError::BufferSize);
+        }
+        Ok(len / blk_sz)
+    }
+
+    /// Data address of `lba`: SDHC/SDXC take block addresses, SDSC byte addresses
+    fn card_address(&self, lba: usize) -> u32 {
+        if self.ocr.high_capacity() {
+            lba as u32
+        } else {
+            (lba * self.block_size() as usize) as u32
+        }
+    }
+
+    /// CMD17/CMD18 or CMD24/CMD25 depending on the direction and the block count
+    fn block_cmd(&self, write: bool, lba: usize, count: usize) -> Command {
+        let address = self.card_address(lba);
+        match (write, count) {
+            (false, 1) => read_single_block(address),
+            (false, _) => read_multiple_block(address, self.auto_stop),
+            (true, 1) => write_single_block(address),
+            (true, _) => write_multiple_block(address, self.auto_stop),
+        }
+    }
+
+    /// The IDMAC is used for word aligned buffers reachable by 32-bit descriptors.
+    fn idmac_mode(&self, addr: usize, len: usize) -> Option<DescMode> {
+        match self.config.dma_mode {
+            DmaMode::Idmac(mode)
+                if addr.is_multiple_of(4)
+                    && (self.config.hooks.virt_to_phys)(addr) + len <= u32::MAX as usize =>
+            {
+                Some(mode)
             }
+            _ => None,
         }
     }
 
-    pub fn write_block(&self, lba: usize, data: &[u8]) -> Result<(), DeviceError> {
-        let cmd = write_single_block(lba as u32);
-        match self.mmc_opt.send_cmd(cmd) {
-            Ok(resp) => {
+    fn idmac_blocks(
+        &self,
+        mode: DescMode,
+        lba: usize,
+        count: usize,
+        addr: usize,
+        write: bool,
+    ) -> Result<(), DeviceError> {
+        let blk_sz = self.block_size() as usize;
+        let max_blk = IdmacRing::max_len() / blk_sz;
+        let mut done = 0;
+        while done < count {
+            let blk = max_blk.min(count - done);
+            let chunk = addr + done * blk_sz;
+            let desc_base = self.idmac_map(mode, chunk, blk * blk_sz, write);
+            self.mmc_opt
+                .start_idmac(desc_base, blk as u32, blk_sz as u32);
+            let cmd = self.block_cmd(write, lba + done, blk);
+            let ret = self.mmc_opt.send_cmd(cmd).and_then(|resp| {
                 let status = resp.card_status();
                 debug!("{status:?}");
-                let blk_sz = self.block_size() as u32;
-                let blk = data.len() as u32 / blk_sz;
-                match self.mmc_opt.write_data(data, blk, blk_sz) {
-                    Ok(_) => Ok(()),
-                    Err(err) => {
-                        debug!("{err:?}");
-                        self.mmc_opt.stop_transmission_ops()?;
-                        Err(DeviceError::IoError)
-                    }
+                self.mmc_opt.wait_idmac()
+            });
+            self.mmc_opt.stop_idmac(ret.is_err())?;
+            self.idmac_unmap(chunk, blk * blk_sz, write);
+            self.finish_transfer(blk, ret)?;
+            done += blk;
+        }
+        Ok(())
+    }
+
+    /// Describe `len` bytes at `addr` to the IDMAC and hand them over: data to write
+    /// must reach memory, a read buffer must not hold dirty lines evicted over the
+    /// DMA data. Returns the physical address of the descriptor list.
+    fn idmac_map(&self, mode: DescMode, addr: usize, len: usize, write: bool) -> usize {
+        let hooks = &self.config.hooks;
+        if write {
+            (hooks.cache_clean)(addr, len);
+        } else {
+            (hooks.cache_invalidate)(addr, len);
+        }
+        self.idmac
+            .prepare(mode, (hooks.virt_to_phys)(addr), len, hooks)
+    }
+
+    /// Take a read buffer back from the IDMAC, dropping lines fetched while it was written
+    fn idmac_unmap(&self, addr: usize, len: usize, write: bool) {
+        if !write {
+            (self.config.hooks.cache_invalidate)(addr, len);
+        }
+    }
+
+    fn finish_transfer(&self, count: usize, ret: Result<(), CardError>) -> Result<(), DeviceError> {
+        match ret {
+            Ok(_) => {
+                if count > 1 && !self.auto_stop {
+                    self.mmc_opt.stop_transmission_ops()?;
                 }
+                // wait for the auto stop or the card programming to release the data line
+                self.mmc_opt.wait_for_data_line()?;
+                Ok(())
             }
             Err(err) => {
                 debug!("{err:?}");
@@ -165,63 +815,44 @@ impl DwMmcHost {
     }
 }
 
-// impl BlockDevice for DwMmcHost {
-//     fn read_block(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
-//         trace!("read block, address: {},", lba);
-//         let cmd = read_single_block(lba as u32);
-//         match self.mmc_opt.send_cmd(cmd) {
-//             Ok(resp) => {
-//                 let status = resp.card_status();
-//                 debug!("{status:?}");
-//                 let blk_sz = self.block_size() as u32;
-//                 let blk = buf.len() as u32 / blk_sz;
-//                 match self.mmc_opt.read_data(buf, blk, blk_sz) {
-//                     Ok(_) => Ok(()),
-//                     Err(err) => {
-//                         debug!("{err:?}");
-//                         self.mmc_opt.stop_transmission_ops()?;
-//                         Err(DeviceError::IoError)
-//                     }
-//                 }
-//             }
-//             Err(err) => {
-//                 debug!("{err:?}");
-//                 self.mmc_opt.stop_transmission_ops()?;
-//                 Err(DeviceError::IoError)
-//             }
-//         }
-//     }
-
-//     fn write_block(&self, lba: usize, data: &[u8]) -> Result<(), DeviceError> {
-//         let cmd = write_single_block(lba as u32);
-//         match self.mmc_opt.send_cmd(cmd) {
-//             Ok(resp) => {
-//                 let status = resp.card_status();
-//                 debug!("{status:?}");
-//                 let blk_sz = self.block_size() as u32;
-//                 let blk = data.len() as u32 / blk_sz;
-//                 match self.mmc_opt.write_data(data, blk, blk_sz) {
-//                     Ok(_) => Ok(()),
-//                     Err(err) => {
-//                         debug!("{err:?}");
-//                         self.mmc_opt.stop_transmission_ops()?;
-//                         Err(DeviceError::IoError)
-//                     }
-//                 }
-//             }
-//             Err(err) => {
-//                 debug!("{err:?}");
-//                 self.mmc_opt.stop_transmission_ops()?;
-//                 Err(DeviceError::IoError)
-//             }
-//         }
-//     }
-
-//     fn block_size(&self) -> BlockSize {
-//         BlockSize::Lb512
-//     }
-
-//     fn information(&self) -> &dyn BlkDevInfo {
-//         todo!()
-//     }
-// }
+impl Device for DwMmcHost {
+    fn close(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn status(&self) -> DeviceStatus {
+        self.status
+    }
+
+    fn reinit(&mut self) -> Result<(), DeviceError> {
+        self.init()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn error_handle(&self) -> DeviceStatus {
+        DeviceStatus::Error
+    }
+}
+
+impl BlockDevice for DwMmcHost {
+    fn read_block(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
+        let count = self.whole_blocks(buf.len())?;
+        self.read_blocks(lba, count, buf)
+    }
+
+    fn write_block(&self, lba: usize, data: &[u8]) -> Result<(), DeviceError> {
+        let count = self.whole_blocks(data.len())?;
+        self.write_blocks(lba, count, data)
+    }
+
+    fn block_size(&self) -> BlockSize {
+        BlockSize::Lb512
+    }
+
+    fn information(&self) -> &dyn BlkDevInfo {
+        &self.info
+    }
+}
@@ -0,0 +1,109 @@
+use lego_device::write_reg;
+use log::{debug, info};
+
+use super::config::Voltages;
+use super::err::CardError;
+use super::mmc_reg::*;
+use super::reg::*;
+use super::sd_reg::BusWidth;
+use super::uhs::{SignalVoltage, Timing};
+use super::DwMmcHost;
+
+/// eMMC bus timing selection, once the bus width is switched
+impl DwMmcHost {
+    /// Fastest timing allowed by EXT_CSD DEVICE_TYPE and the board:
+    /// HS400, HS200, then High Speed
+    pub(crate) fn mmc_timing(&mut self) -> Result<Timing, CardError> {
+        let hs200 = self.ext_csd.hs200()
+            && self.config.bus_width != BusWidth::One
+            && self.config.mmc_timings.contains(&Timing::Hs200)
+            && self.config.voltages.contains(Voltages::v180)
+            && self.config.hooks.sample_phase.is_some();
+        let hs400 = hs200
+            && self.ext_csd.hs400()
+            && self.config.bus_width == BusWidth::Eight
+            && self.config.mmc_timings.contains(&Timing::Hs400)
+            && self.config.hooks.timing.is_some();
+        if hs200 && self.mmc_signal_180() {
+            self.ext_csd_switch(EXT_CSD_HS_TIMING, EXT_CSD_TIMING_HS200)?;
+            if !hs400 {
+                info!("mmc timing: HS200");
+                return Ok(Timing::Hs200);
+            }
+            self.mmc_hs400()?;
+            info!("mmc timing: HS400");
+            return Ok(Timing::Hs400);
+        }
+        if self.ext_csd.hs52() {
+            self.ext_csd_switch(EXT_CSD_HS_TIMING, EXT_CSD_TIMING_HS)?;
+            info!("mmc timing: high speed");
+            return Ok(Timing::HighSpeed);
+        }
+        Ok(Timing::Legacy)
+    }
+
+    /// HS400 is entered from a tuned HS200: back to High Speed at 52MHz or less,
+    /// 8-bit DDR, then HS400 at the HS200 clock. The controller samples reads on the
+    /// data strobe from then on, whose input and delay line the timing hook sets up.
+    fn mmc_hs400(&mut self) -> Result<(), CardError> {
+        self.set_card_clock(Timing::Hs200.max_clock(false))?;
+        // vendor delay lines take part in tuning, they must be set up first
+        if let Some(timing) = self.config.hooks.timing {
+            timing(Timing::Hs200);
+        }
+        self.execute_tuning()?;
+        self.hs_timing_switch(Timing::HighSpeed)?;
+        self.ext_csd_switch(EXT_CSD_BUS_WIDTH, EXT_CSD_BUS_WIDTH_8_DDR)?;
+        self.mmc_opt.set_uhs(UHS_DDR, true);
+        debug!("mmc in DDR52");
+        self.hs_timing_switch(Timing::Hs400)
+    }
+
+    /// Move HS_TIMING to `timing` and the host along with it before the CMD13 status
+    /// check, the card no longer answers at the clock of the timing it left
+    fn hs_timing_switch(&mut self, timing: Timing) -> Result<(), CardError> {
+        let value = match timing {
+            Timing::Hs400 => EXT_CSD_TIMING_HS400,
+            _ => EXT_CSD_TIMING_HS,
+        };
+        self.mmc_opt.send_switch(EXT_CSD_HS_TIMING, value)?;
+        self.mmc_opt.wait_for_data_line()?;
+        if timing == Timing::Hs400 {
+            write_reg::<u32>(self.sdio_base, REG_EMMC_DDR, EMMC_DDR_HS400);
+        }
+        self.set_card_clock(timing.max_clock(false))?;
+        self.mmc_opt.check_switch(
+            self.rca,
+            EXT_CSD_HS_TIMING,
+            value,
+            self.ext_csd.generic_cmd6_time_ms(),
+        )
+    }
+
+    /// Leave HS400 sampling behind before the bus restarts at legacy timing. Only
+    /// touches REG_EMMC_DDR once HS400 was entered, older controllers lack it.
+    pub(crate) fn leave_hs400(&self) {
+        if self.timing == Timing::Hs400 {
+            write_reg::<u32>(self.sdio_base, REG_EMMC_DDR, 0);
+        }
+    }
+
+    /// eMMC I/O runs at VCCQ, no CMD11 handshake: move the supply and the pads
+    /// to 1.8V. Without a regulator hook VCCQ is taken to be wired at 1.8V.
+    fn mmc_signal_180(&mut self) -> bool {
+        if let Some(regulator) = self.config.hooks.regulator {
+            if !regulator(SignalVoltage::V180) {
+                return false;
+            }
+        }
+        self.mmc_opt.set_uhs(UHS_VOLT_18, true);
+        self.signal_voltage = SignalVoltage::V180;
+        true
+    }
+
+    /// CMD6 write of one EXT_CSD byte, busy for at most GENERIC_CMD6_TIME
+    pub(crate) fn ext_csd_switch(&self, index: u8, value: u8) -> Result<(), CardError> {
+        self.mmc_opt
+            .mmc_switch(self.rca, index, value, self.ext_csd.generic_cmd6_time_ms())
+    }
+}
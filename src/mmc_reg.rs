@@ -0,0 +1,238 @@
+use core::fmt::Debug;
+
+/// CMD6 SWITCH access modes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[allow(unused)]
+pub enum SwitchAccess {
+    CommandSet = 0,
+    SetBits = 1,
+    ClearBits = 2,
+    WriteByte = 3,
+}
+
+/// Hardware partitions of an eMMC, as encoded in PARTITION_CONFIG PARTITION_ACCESS
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MmcPartition {
+    User = 0,
+    Boot1 = 1,
+    Boot2 = 2,
+    Rpmb = 3,
+    Gp1 = 4,
+    Gp2 = 5,
+    Gp3 = 6,
+    Gp4 = 7,
+}
+
+// EXT_CSD byte offsets, Ref JESD84-B51 Table 77
+const EXT_CSD_GP_SIZE_MULT: usize = 143;
+const EXT_CSD_RPMB_SIZE_MULT: usize = 168;
+const EXT_CSD_ERASE_GROUP_DEF: usize = 175;
+pub const EXT_CSD_PARTITION_CONFIG: u8 = 179;
+pub const EXT_CSD_BUS_WIDTH: u8 = 183;
+pub const EXT_CSD_HS_TIMING: u8 = 185;
+const EXT_CSD_REV: usize = 192;
+const EXT_CSD_DEVICE_TYPE: usize = 196;
+const EXT_CSD_PARTITION_SWITCH_TIME: usize = 199;
+const EXT_CSD_SEC_COUNT: usize = 212;
+const EXT_CSD_HC_WP_GRP_SIZE: usize = 221;
+const EXT_CSD_ERASE_TIMEOUT_MULT: usize = 223;
+const EXT_CSD_HC_ERASE_GRP_SIZE: usize = 224;
+const EXT_CSD_BOOT_SIZE_MULT: usize = 226;
+const EXT_CSD_SEC_FEATURE_SUPPORT: usize = 231;
+const EXT_CSD_TRIM_MULT: usize = 232;
+const EXT_CSD_GENERIC_CMD6_TIME: usize = 248;
+
+/// EXT_CSD BUS_WIDTH values
+pub const EXT_CSD_BUS_WIDTH_4: u8 = 1;
+pub const EXT_CSD_BUS_WIDTH_8: u8 = 2;
+pub const EXT_CSD_BUS_WIDTH_8_DDR: u8 = 6;
+
+/// EXT_CSD HS_TIMING values
+pub const EXT_CSD_TIMING_HS: u8 = 1;
+pub const EXT_CSD_TIMING_HS200: u8 = 2;
+pub const EXT_CSD_TIMING_HS400: u8 = 3;
+
+/// eMMC Extended CSD register, read with CMD8 SEND_EXT_CSD
+#[derive(Copy, Clone)]
+pub struct ExtCsd([u8; 512]);
+
+impl From<[u8; 512]> for ExtCsd {
+    fn from(value: [u8; 512]) -> Self {
+        Self(value)
+    }
+}
+
+impl Default for ExtCsd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExtCsd {
+    pub const fn new() -> Self {
+        Self([0; 512])
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.0[EXT_CSD_REV]
+    }
+
+    /// Number of 512 byte sectors, only valid for devices above 2GB
+    pub fn sector_count(&self) -> u32 {
+        u32::from_le_bytes([
+            self.0[EXT_CSD_SEC_COUNT],
+            self.0[EXT_CSD_SEC_COUNT + 1],
+            self.0[EXT_CSD_SEC_COUNT + 2],
+            self.0[EXT_CSD_SEC_COUNT + 3],
+        ])
+    }
+
+    pub fn partition_config(&self) -> u8 {
+        self.0[EXT_CSD_PARTITION_CONFIG as usize]
+    }
+
+    /// Partition enabled for boot, 0 means boot is disabled
+    pub fn boot_partition_enable(&self) -> u8 {
+        (self.partition_config() >> 3) & 0x7
+    }
+
+    /// Partition currently reached by data commands
+    pub fn partition_access(&self) -> u8 {
+        self.partition_config() & 0x7
+    }
+
+    /// Size of each boot partition in bytes, in units of 128KB
+    pub fn boot_size(&self) -> u64 {
+        u64::from(self.0[EXT_CSD_BOOT_SIZE_MULT]) << 17
+    }
+
+    /// Size of the RPMB partition in bytes, in units of 128KB
+    pub fn rpmb_size(&self) -> u64 {
+        u64::from(self.0[EXT_CSD_RPMB_SIZE_MULT]) << 17
+    }
+
+    /// Size of general purpose partition `n` (0..4) in bytes, in units of
+    /// HC_WP_GRP_SIZE x HC_ERASE_GRP_SIZE x 512KB
+    pub fn gp_size(&self, n: usize) -> u64 {
+        let base = EXT_CSD_GP_SIZE_MULT + n * 3;
+        let mult = u64::from(self.0[base])
+            | u64::from(self.0[base + 1]) << 8
+            | u64::from(self.0[base + 2]) << 16;
+        (mult
+            * u64::from(self.0[EXT_CSD_HC_WP_GRP_SIZE])
+            * u64::from(self.0[EXT_CSD_HC_ERASE_GRP_SIZE]))
+            << 19
+    }
+
+    /// Size in bytes of a hardware partition, the user area is only valid above 2GB
+    pub fn partition_size(&self, part: MmcPartition) -> u64 {
+        match part {
+            MmcPartition::User => u64::from(self.sector_count()) << 9,
+            MmcPartition::Boot1 | MmcPartition::Boot2 => self.boot_size(),
+            MmcPartition::Rpmb => self.rpmb_size(),
+            MmcPartition::Gp1 => self.gp_size(0),
+            MmcPartition::Gp2 => self.gp_size(1),
+            MmcPartition::Gp3 => self.gp_size(2),
+            MmcPartition::Gp4 => self.gp_size(3),
+        }
+    }
+
+    /// Maximum time of a PARTITION_CONFIG switch in milliseconds, zero if unspecified
+    pub fn partition_switch_time_ms(&self) -> usize {
+        usize::from(self.0[EXT_CSD_PARTITION_SWITCH_TIME]) * 10
+    }
+
+    /// Maximum time of other CMD6 switches in milliseconds, zero if unspecified
+    pub fn generic_cmd6_time_ms(&self) -> usize {
+        usize::from(self.0[EXT_CSD_GENERIC_CMD6_TIME]) * 10
+    }
+
+    /// Erases use the high capacity erase group and timeout instead of the CSD ones
+    pub fn erase_group_def(&self) -> bool {
+        self.0[EXT_CSD_ERASE_GROUP_DEF] & 0x1 != 0
+    }
+
+    /// High capacity erase group in 512 byte blocks, in units of 512KB
+    pub fn hc_erase_group_blocks(&self) -> u32 {
+        u32::from(self.0[EXT_CSD_HC_ERASE_GRP_SIZE]) << 10
+    }
+
+    /// Erase time per high capacity erase group in milliseconds, zero if unspecified
+    pub fn erase_timeout_ms(&self) -> usize {
+        usize::from(self.0[EXT_CSD_ERASE_TIMEOUT_MULT]) * 300
+    }
+
+    /// Trim and discard time per erase group in milliseconds, zero if unspecified
+    pub fn trim_timeout_ms(&self) -> usize {
+        usize::from(self.0[EXT_CSD_TRIM_MULT]) * 300
+    }
+
+    /// SEC_GB_CL_EN, trim is supported
+    pub fn trim_support(&self) -> bool {
+        self.0[EXT_CSD_SEC_FEATURE_SUPPORT] & 0x10 != 0
+    }
+
+    /// Discard came with eMMC 4.5, EXT_CSD revision 6
+    pub fn discard_support(&self) -> bool {
+        self.revision() >= 6
+    }
+
+    pub fn bus_width(&self) -> u8 {
+        self.0[EXT_CSD_BUS_WIDTH as usize]
+    }
+
+    pub fn hs_timing(&self) -> u8 {
+        self.0[EXT_CSD_HS_TIMING as usize]
+    }
+
+    pub fn device_type(&self) -> u8 {
+        self.0[EXT_CSD_DEVICE_TYPE]
+    }
+
+    /// High speed at 26MHz
+    pub fn hs26(&self) -> bool {
+        self.device_type() & 0x1 != 0
+    }
+
+    /// High speed at 52MHz
+    pub fn hs52(&self) -> bool {
+        self.device_type() & 0x2 != 0
+    }
+
+    /// High speed DDR at 52MHz, 1.8V or 3V I/O
+    pub fn ddr52(&self) -> bool {
+        self.device_type() & 0x4 != 0
+    }
+
+    /// HS200 SDR at 200MHz, 1.8V I/O
+    pub fn hs200(&self) -> bool {
+        self.device_type() & 0x10 != 0
+    }
+
+    /// HS400 DDR at 200MHz, 1.8V I/O
+    pub fn hs400(&self) -> bool {
+        self.device_type() & 0x40 != 0
+    }
+
+    pub fn raw(&self) -> &[u8; 512] {
+        &self.0
+    }
+}
+
+impl Debug for ExtCsd {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EXT_CSD: Extended CSD")
+            .field("Revision", &self.revision())
+            .field("Sector Count", &self.sector_count())
+            .field("Partition Config", &self.partition_config())
+            .field("Boot Partition Size", &self.boot_size())
+            .field("RPMB Size", &self.rpmb_size())
+            .field("Bus Width", &self.bus_width())
+            .field("HS Timing", &self.hs_timing())
+            .field("HS 52MHz", &self.hs52())
+            .field("DDR 52MHz", &self.ddr52())
+            .field("HS200", &self.hs200())
+            .field("HS400", &self.hs400())
+            .finish()
+    }
+}
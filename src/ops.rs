@@ -1,6 +1,9 @@
 use crate::cmd::*;
+use crate::irq::HostIrq;
+use crate::mmc_reg::*;
 use crate::reg::*;
 use crate::sd_reg::*;
+use crate::uhs::SignalVoltage;
 use crate::CountDown;
 use log::{debug, error};
 
@@ -11,6 +14,10 @@ use super::err::*;
 pub(super) struct MmcOperate {
     sdio_base: usize,
     get_macros: fn() -> usize,
+    fifo_width: FifoWidth,
+    fifo_depth: u32,
+    irq: Option<&'static HostIrq>,
+    wait: fn(),
 }
 
 impl MmcOperate {
@@ -18,8 +25,27 @@ impl MmcOperate {
         Self {
             sdio_base,
             get_macros,
+            fifo_width: FifoWidth::B32,
+            fifo_depth: 0,
+            irq: None,
+            wait: core::hint::spin_loop,
         }
     }
+
+    pub fn irq(&self) -> Option<&'static HostIrq> {
+        self.irq
+    }
+
+    /// Merge the status acknowledged by `irq` into the polled status, calling `wait` between polls
+    pub fn set_irq(&mut self, irq: Option<&'static HostIrq>, wait: fn()) {
+        self.irq = irq;
+        self.wait = wait;
+    }
+
+    /// Width of REG_DATA accesses, decoded from HCON H_DATA_WIDTH
+    pub fn set_fifo_width(&mut self, width: FifoWidth) {
+        self.fifo_width = width;
+    }
     fn wait_for_cmd_line(&self) -> Result<(), Timeout> {
         if !self.wait_for(0xFF, || {
             read_reg::<u32>(self.sdio_base, REG_CMD) & CmdMask::start_cmd.bits() == 0
@@ -30,7 +56,7 @@ impl MmcOperate {
         }
     }
 
-    fn wait_for_data_line(&self) -> Result<(), Timeout> {
+    pub fn wait_for_data_line(&self) -> Result<(), Timeout> {
         if self.wait_for(DATA_TMOUT_DEFUALT, || {
             read_reg::<u32>(self.sdio_base, REG_STATUS) & StatusMask::data_busy.bits() == 0
         }) {
@@ -40,10 +66,18 @@ impl MmcOperate {
         }
     }
 
+    /// The card holds DAT0 low, busy programming or applying a switch
+    pub fn data_busy(&self) -> bool {
+        read_reg::<u32>(self.sdio_base, REG_STATUS) & StatusMask::data_busy.bits() != 0
+    }
+
+    /// The controller raises the busy clear interrupt enabled by `set_card_threshold`
+    pub fn busy_clear_irq(&self) -> bool {
+        read_reg::<u32>(self.sdio_base, REG_VERID) & VERID_VERSION >= VERID_BUSY_CLR_INT
+    }
+
     fn wait_for_cmd_done(&self) -> Result<(), Timeout> {
-        if self.wait_for(0xFF, || {
-            read_reg::<u32>(self.sdio_base, REG_RINTSTS) & InterruptMask::cmd.bits() != 0
-        }) {
+        if self.wait_for(0xFF, || self.int_status() & InterruptMask::cmd.bits() != 0) {
             Ok(())
         } else {
             Err(Timeout::WaitCmdDone)
@@ -59,26 +93,39 @@ impl MmcOperate {
     }
 
     pub fn send_cmd(&self, cmd: Command) -> Result<Response, CardError> {
+        self.start_cmd(&cmd)?;
+        self.wait_for_cmd_done()?;
+        let resp = self.cmd_response(&cmd)?;
+        self.delay_macros(100);
+        Ok(resp)
+    }
+
+    /// Hand `cmd` to the controller without waiting for it to complete
+    pub fn start_cmd(&self, cmd: &Command) -> Result<(), CardError> {
         self.wait_for_cmd_line()?;
-        write_reg(self.sdio_base, REG_RINTSTS, InterruptMask::all().bits());
+        self.clear_int(InterruptMask::all().bits());
 
         if cmd.data_exp() {
             self.wait_for_data_line()?;
         }
         write_reg(self.sdio_base, REG_CMDARG, cmd.arg());
         write_reg(self.sdio_base, REG_CMD, cmd.cmd());
-        self.wait_for_cmd_done()?;
+        Ok(())
+    }
+
+    /// Collect the response of a command whose command done interrupt has been raised
+    pub fn cmd_response(&self, cmd: &Command) -> Result<Response, CardError> {
         let resp = if cmd.resp_exp() {
-            let mask: u32 = read_reg(self.sdio_base, REG_RINTSTS);
+            let mask = self.int_status();
             if mask & InterruptMask::rto.bits() != 0 {
-                write_reg(self.sdio_base, REG_RINTSTS, mask);
+                self.clear_int(mask);
                 error!(
                     "Response Timeout, mask: {:?}",
                     InterruptMask::from_bits(mask).unwrap()
                 );
                 return Err(Interrupt::ResponseTimeout.into());
             } else if mask & InterruptMask::re.bits() != 0 {
-                write_reg(self.sdio_base, REG_RINTSTS, mask);
+                self.clear_int(mask);
                 error!(
                     "Response Error, mask : {:?}",
                     InterruptMask::from_bits(mask).unwrap()
@@ -106,68 +153,213 @@ impl MmcOperate {
         if cmd.data_exp() {
             self.wait_reset(ControlMask::fifo_reset.bits())?;
         }
-        self.delay_macros(100);
         Ok(resp)
     }
 
-    pub fn read_data(&self, buf: &mut [u8], blk: u32, blk_sz: u32) -> Result<(), CardError> {
+    /// Watermark left by the reset value or the bootloader: RX_WMark is depth - 1 after reset
+    pub fn fifoth(&self) -> FifoThreshold {
+        FifoThreshold::from_bits(read_reg::<u32>(self.sdio_base, REG_FIFOTH))
+    }
+
+    /// Record the FIFO depth in words and program the default watermarks
+    pub fn set_fifo_depth(&mut self, depth: u32) {
+        self.fifo_depth = depth;
+        let fifoth = FifoThreshold::new(depth);
+        debug!("{fifoth:?}");
+        write_reg::<u32>(self.sdio_base, REG_FIFOTH, fifoth.bits());
+    }
+
+    /// Program the size of the next data command, must precede the command itself.
+    pub fn set_transfer_size(&self, blk: u32, blk_sz: u32) {
+        let fifoth = FifoThreshold::for_block(self.fifo_depth, self.fifo_width, blk_sz);
+        write_reg::<u32>(self.sdio_base, REG_FIFOTH, fifoth.bits());
         write_reg::<u32>(self.sdio_base, REG_BLKSIZ, blk_sz);
-        write_reg::<u32>(self.sdio_base, REG_BYTCNT, blk_sz * blk);
-        let size = (blk * blk_sz) as usize;
+        write_reg::<u32>(self.sdio_base, REG_BYTCNT, blk * blk_sz);
+    }
+
+    pub fn read_data(&self, buf: &mut [u8]) -> Result<(), CardError> {
         let mut offset = 0;
         let timer = CountDown::new(DATA_TMOUT_DEFUALT, self.get_macros);
         loop {
-            let mask = read_reg::<u32>(self.sdio_base, REG_RINTSTS);
-            if offset == size && mask & InterruptMask::dto.bits() != 0 {
+            if self.read_data_step(buf, &mut offset)? {
                 break;
             }
-            Interrupt::check(mask)?;
-            self.delay_macros(10);
             if timer.timeout() {
                 return Err(CardError::DataTransferTimeout);
             }
-            if mask & (InterruptMask::rxdr | InterruptMask::dto).bits() != 0 {
-                while (read_reg::<u32>(self.sdio_base, REG_STATUS) >> 17) & 0x1FFF != 0 {
-                    buf[offset] = read_reg::<u8>(self.sdio_base, REG_DATA + offset);
-                    offset += 1;
-                }
-                write_reg::<u32>(self.sdio_base, REG_RINTSTS, InterruptMask::rxdr.bits());
-            }
+            self.idle();
         }
-        write_reg::<u32>(
-            self.sdio_base,
-            REG_RINTSTS,
-            read_reg::<u32>(self.sdio_base, REG_RINTSTS),
-        );
+        self.clear_int(self.int_status());
         Ok(())
     }
 
-    pub fn write_data(&self, buf: &[u8], blk: u32, blk_sz: u32) -> Result<(), CardError> {
-        write_reg::<u32>(self.sdio_base, REG_BLKSIZ, blk_sz);
-        write_reg::<u32>(self.sdio_base, REG_BYTCNT, blk * blk_sz);
+    pub fn write_data(&self, buf: &[u8]) -> Result<(), CardError> {
+        let mut offset = 0;
         let timer = CountDown::new(DATA_TMOUT_DEFUALT, self.get_macros);
         loop {
-            let mask = read_reg::<u32>(self.sdio_base, REG_RINTSTS);
-            if InterruptMask::dto.bits() & mask != 0 {
+            if self.write_data_step(buf, &mut offset)? {
                 break;
             }
-            Interrupt::check(mask)?;
-            self.delay_macros(10);
             if timer.timeout() {
                 return Err(CardError::DataTransferTimeout);
             }
-            if mask & InterruptMask::txdr.bits() != 0 {
-                for (offset, byte) in buf.iter().enumerate() {
-                    write_reg::<u8>(self.sdio_base, REG_DATA + offset, *byte);
-                }
-                write_reg::<u32>(self.sdio_base, REG_RINTSTS, InterruptMask::txdr.bits());
+            self.idle();
+        }
+        self.clear_int(self.int_status());
+        Ok(())
+    }
+
+    /// Service one round of PIO read interrupts, returns true once the data phase is over
+    pub fn read_data_step(&self, buf: &mut [u8], offset: &mut usize) -> Result<bool, CardError> {
+        let mask = self.int_status();
+        Interrupt::check(mask)?;
+        if mask & (InterruptMask::rxdr | InterruptMask::dto).bits() != 0 {
+            // clear before draining so a watermark crossed meanwhile is not lost
+            self.clear_int(InterruptMask::rxdr.bits());
+            *offset += self.read_fifo(&mut buf[*offset..]);
+        }
+        Ok(*offset == buf.len() && mask & InterruptMask::dto.bits() != 0)
+    }
+
+    /// Service one round of PIO write interrupts, returns true once the data phase is over
+    pub fn write_data_step(&self, buf: &[u8], offset: &mut usize) -> Result<bool, CardError> {
+        let mask = self.int_status();
+        Interrupt::check(mask)?;
+        if InterruptMask::dto.bits() & mask != 0 {
+            return Ok(true);
+        }
+        if mask & InterruptMask::txdr.bits() != 0 {
+            // also cleared once the buffer is queued, nothing is left to fill the FIFO with
+            self.clear_int(InterruptMask::txdr.bits());
+            if *offset < buf.len() {
+                *offset += self.write_fifo(&buf[*offset..]);
             }
         }
+        Ok(false)
+    }
+
+    /// Number of FIFO words currently held by the controller
+    fn fifo_count(&self) -> usize {
+        ((read_reg::<u32>(self.sdio_base, REG_STATUS) & StatusMask::fifo_count.bits()) >> 17)
+            as usize
+    }
+
+    /// Drain the words present in the FIFO into `buf`, returns the bytes stored.
+    fn read_fifo(&self, buf: &mut [u8]) -> usize {
+        let width = self.fifo_width.bytes();
+        let mut offset = 0;
+        for _ in 0..self.fifo_count() {
+            if offset == buf.len() {
+                break;
+            }
+            let word = match self.fifo_width {
+                FifoWidth::B16 => read_reg::<u16>(self.sdio_base, REG_DATA) as u64,
+                FifoWidth::B32 => read_reg::<u32>(self.sdio_base, REG_DATA) as u64,
+                FifoWidth::B64 => read_reg::<u64>(self.sdio_base, REG_DATA),
+            };
+            // go through bytes so unaligned caller buffers are fine
+            let len = width.min(buf.len() - offset);
+            buf[offset..offset + len].copy_from_slice(&word.to_le_bytes()[..len]);
+            offset += len;
+        }
+        offset
+    }
+
+    /// Fill the free FIFO space from `buf`, returns the bytes consumed.
+    fn write_fifo(&self, buf: &[u8]) -> usize {
+        let width = self.fifo_width.bytes();
+        let free = (self.fifo_depth as usize).saturating_sub(self.fifo_count());
+        let mut offset = 0;
+        for _ in 0..free {
+            if offset == buf.len() {
+                break;
+            }
+            let len = width.min(buf.len() - offset);
+            let mut bytes = [0u8; 8];
+            bytes[..len].copy_from_slice(&buf[offset..offset + len]);
+            let word = u64::from_le_bytes(bytes);
+            match self.fifo_width {
+                FifoWidth::B16 => write_reg::<u16>(self.sdio_base, REG_DATA, word as u16),
+                FifoWidth::B32 => write_reg::<u32>(self.sdio_base, REG_DATA, word as u32),
+                FifoWidth::B64 => write_reg::<u64>(self.sdio_base, REG_DATA, word),
+            }
+            offset += len;
+        }
+        offset
+    }
+
+    /// Program the transfer size and hand the descriptor list at `desc_base` to the IDMAC.
+    pub fn start_idmac(&self, desc_base: usize, blk: u32, blk_sz: u32) {
+        self.clear_dma(IDSTS_CLEAR);
+        self.set_transfer_size(blk, blk_sz);
+        write_reg::<u32>(self.sdio_base, REG_DBADDR, desc_base as u32);
+        let ctrl = read_reg::<u32>(self.sdio_base, REG_CTRL);
+        write_reg::<u32>(
+            self.sdio_base,
+            REG_CTRL,
+            ctrl | (ControlMask::use_internal_dmac | ControlMask::dma_enable).bits(),
+        );
+        let bmod = read_reg::<u32>(self.sdio_base, REG_BMOD);
         write_reg::<u32>(
             self.sdio_base,
-            REG_RINTSTS,
-            read_reg::<u32>(self.sdio_base, REG_RINTSTS),
+            REG_BMOD,
+            bmod | (BusMode::de | BusMode::fb).bits(),
         );
+        // resume the IDMAC in case it suspended on an unowned descriptor
+        write_reg::<u32>(self.sdio_base, REG_PLDMND, 1);
+    }
+
+    /// Wait for the IDMAC to finish the descriptor list and the card to finish the data phase.
+    pub fn wait_idmac(&self) -> Result<(), CardError> {
+        let timer = CountDown::new(DATA_TMOUT_DEFUALT, self.get_macros);
+        let mut dma_done = false;
+        loop {
+            if self.idmac_step(&mut dma_done)? {
+                break;
+            }
+            if timer.timeout() {
+                return Err(CardError::DataTransferTimeout);
+            }
+            self.idle();
+        }
+        self.clear_int(self.int_status());
+        Ok(())
+    }
+
+    /// Check the IDMAC and data status once, returns true once both are done
+    pub fn idmac_step(&self, dma_done: &mut bool) -> Result<bool, CardError> {
+        let idsts = self.dma_status();
+        self.clear_dma(idsts & IDSTS_CLEAR);
+        if let Err(err) = Dma::check(idsts) {
+            error!(
+                "Idmac error, status: {:?}",
+                DmaStatus::from_bits_truncate(idsts)
+            );
+            // card errors are reported in detail by RINTSTS
+            Interrupt::check(self.int_status())?;
+            return Err(err.into());
+        }
+        *dma_done |= idsts & (DmaStatus::ri | DmaStatus::ti).bits() != 0;
+        let mask = self.int_status();
+        Interrupt::check(mask)?;
+        Ok(*dma_done && mask & InterruptMask::dto.bits() != 0)
+    }
+
+    /// Give the FIFO back to the CPU, resetting the IDMAC after a failed transfer.
+    pub fn stop_idmac(&self, reset: bool) -> Result<(), Timeout> {
+        let ctrl = read_reg::<u32>(self.sdio_base, REG_CTRL)
+            & !(ControlMask::use_internal_dmac | ControlMask::dma_enable).bits();
+        let bmod = read_reg::<u32>(self.sdio_base, REG_BMOD) & !BusMode::de.bits();
+        write_reg::<u32>(self.sdio_base, REG_BMOD, bmod);
+        if reset {
+            let reset_mask = (ControlMask::dma_reset | ControlMask::fifo_reset).bits();
+            write_reg::<u32>(self.sdio_base, REG_CTRL, ctrl | reset_mask);
+            self.wait_reset(reset_mask)?;
+            write_reg::<u32>(self.sdio_base, REG_BMOD, bmod | BusMode::swr.bits());
+            self.clear_dma(IDSTS_CLEAR);
+        } else {
+            write_reg::<u32>(self.sdio_base, REG_CTRL, ctrl);
+        }
         Ok(())
     }
 
@@ -191,25 +383,36 @@ impl MmcOperate {
         Ok(())
     }
 
-    pub fn check_version(&self) -> Result<Cic, CardError> {
+    /// CMD8, `None` when the card does not answer: SD 1.x or MMC
+    pub fn check_version(&self) -> Result<Option<Cic>, CardError> {
         self.delay_milli(10);
         let cmd = send_if_cond(1, 0xAA);
-        let cic = self.send_cmd(cmd)?.cic();
+        let cic = match self.send_cmd(cmd) {
+            Ok(resp) => resp.cic(),
+            Err(err) if err.is_no_response() => {
+                debug!("no response to CMD8, sd 1.x or mmc");
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
         if cic.voltage_accepted() == 1 && cic.pattern() == 0xAA {
             debug!("sd vision 2.0");
-            Ok(cic)
+            Ok(Some(cic))
         } else {
             Err(CardError::VoltagePattern)
         }
     }
 
-    pub fn check_v18_sdhc(&self) -> Result<Ocr, CardError> {
+    /// ACMD41 with the OCR voltage `window`, `hcs` announces high capacity and `s18r`
+    /// asks for 1.8V signaling, both are only allowed after CMD8
+    pub fn check_v18_sdhc(&self, hcs: bool, s18r: bool, window: u32) -> Result<Ocr, CardError> {
         self.delay_milli(10);
+        let timer = CountDown::new(CARD_READY_TMOUT, self.get_macros);
         let ocr = loop {
             let cmd = app_cmd(0);
             let status = self.send_cmd(cmd)?.card_status();
             debug!("{status:?}");
-            let cmd = sd_send_op_cond(true, true);
+            let cmd = sd_send_op_cond(hcs, s18r, window);
             let ocr = self.send_cmd(cmd)?.ocr();
             if !ocr.is_busy() {
                 if ocr.high_capacity() {
@@ -220,11 +423,142 @@ impl MmcOperate {
                 }
                 break ocr;
             }
+            if timer.timeout() {
+                return Err(Timeout::WaitCardReady.into());
+            }
             self.delay_milli(2);
         };
         Ok(ocr)
     }
 
+    /// CMD11 and the switch to 1.8V signaling, `regulator` moves the I/O supply.
+    ///
+    /// The card answers CMD11 and drives CMD and DAT[3:0] low; the clock is gated
+    /// while the supply settles, and the controller raises the voltage switch
+    /// interrupt (HTO) once the card releases the lines with the clock back on.
+    pub fn voltage_switch(&self, regulator: fn(SignalVoltage) -> bool) -> Result<(), CardError> {
+        let volt_int = InterruptMask::hto.bits();
+        let cmd = voltage_switch();
+        self.start_cmd(&cmd)?;
+        if !self.wait_for(0xFF, || {
+            self.int_status() & (InterruptMask::cmd.bits() | volt_int) != 0
+        }) {
+            return Err(Timeout::WaitCmdDone.into());
+        }
+        let status = self.cmd_response(&cmd)?.card_status();
+        debug!("{status:?}");
+        self.clear_int(InterruptMask::cmd.bits() | volt_int);
+        self.clock_enable(false, true)?;
+        if !regulator(SignalVoltage::V180) {
+            error!("regulator failed to switch to 1.8V");
+            return Err(CardError::VoltageSwitch);
+        }
+        self.set_uhs(UHS_VOLT_18, true);
+        // the supply must be stable for at least 5ms before the clock restarts
+        self.delay_milli(5);
+        self.clock_enable(true, true)?;
+        if !self.wait_for(10, || self.int_status() & volt_int != 0) {
+            error!("card did not release the data lines after the voltage switch");
+            return Err(CardError::VoltageSwitch);
+        }
+        self.clear_int(InterruptMask::all().bits());
+        if read_reg::<u32>(self.sdio_base, REG_STATUS) & StatusMask::data_busy.bits() != 0 {
+            return Err(CardError::VoltageSwitch);
+        }
+        debug!("switched to 1.8V signaling");
+        Ok(())
+    }
+
+    /// Card read threshold and busy clear interrupt: the controller only starts reading
+    /// a block once `threshold` bytes fit in the FIFO, so the clock never has to stop
+    /// mid block at HS200 and above. A zero threshold disables the thresholds.
+    pub fn set_card_threshold(&self, threshold: u32, write: bool) {
+        let mut ctl = CardThrCtl::busy_clr_int_en;
+        if threshold != 0 {
+            ctl |= CardThrCtl::card_rd_thr_en;
+            if write {
+                ctl |= CardThrCtl::card_wr_thr_en;
+            }
+        }
+        let bits = ctl.bits() | (threshold << 16) & CardThrCtl::card_threshold.bits();
+        write_reg::<u32>(self.sdio_base, REG_CARD_THR_CTL, bits);
+    }
+
+    /// Bytes the FIFO holds
+    pub fn fifo_bytes(&self) -> u32 {
+        self.fifo_depth * self.fifo_width.bytes() as u32
+    }
+
+    /// Set or clear `mask` in REG_UHS
+    pub fn set_uhs(&self, mask: u32, enable: bool) {
+        let uhs = read_reg::<u32>(self.sdio_base, REG_UHS);
+        let uhs = if enable { uhs | mask } else { uhs & !mask };
+        write_reg::<u32>(self.sdio_base, REG_UHS, uhs);
+    }
+
+    /// Gate the card clock without touching the divider
+    fn clock_enable(&self, enable: bool, volt_switch: bool) -> Result<(), Timeout> {
+        let cmd = if volt_switch {
+            up_clk_volt_switch()
+        } else {
+            up_clk()
+        };
+        self.wait_for_cmd_line()?;
+        write_reg::<u32>(self.sdio_base, REG_CLKENA, u32::from(enable));
+        write_reg::<u32>(self.sdio_base, REG_CMDARG, 0);
+        write_reg::<u32>(self.sdio_base, REG_CMD, cmd.cmd());
+        self.wait_for_cmd_line()
+    }
+
+    /// Read one tuning block with CMD19 and compare it with `pattern`.
+    /// A CRC error or timeout only fails this sample phase.
+    pub fn tuning_block(&self, cmd: Command, pattern: &[u8]) -> Result<bool, CardError> {
+        let mut buf = [0u8; 128];
+        let buf = &mut buf[..pattern.len()];
+        self.set_transfer_size(1, pattern.len() as u32);
+        let ret = self
+            .send_cmd(cmd)
+            .and_then(|_| self.read_data(buf))
+            .and_then(|_| Ok(self.wait_for_data_line()?));
+        match ret {
+            Ok(_) => Ok(buf == pattern),
+            Err(err) => {
+                debug!("tuning block: {err:?}");
+                self.reset_fifo()?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Drop the words and the interrupts a failed read left behind, so the next data
+    /// command starts from an empty FIFO
+    pub fn reset_fifo(&self) -> Result<(), Timeout> {
+        self.clear_int(InterruptMask::all().bits());
+        write_reg::<u32>(
+            self.sdio_base,
+            REG_CTRL,
+            read_reg::<u32>(self.sdio_base, REG_CTRL) | ControlMask::fifo_reset.bits(),
+        );
+        self.wait_reset(ControlMask::fifo_reset.bits())
+    }
+
+    /// CMD1 with the OCR voltage `window`, power up of MMC cards which do not know ACMD41
+    pub fn check_mmc_ocr(&self, window: u32) -> Result<Ocr, CardError> {
+        self.delay_milli(10);
+        let timer = CountDown::new(CARD_READY_TMOUT, self.get_macros);
+        loop {
+            let ocr = self.send_cmd(send_op_cond(MMC_OCR_ARG | window))?.ocr();
+            if !ocr.is_busy() {
+                debug!("{:?}", ocr);
+                break Ok(ocr);
+            }
+            if timer.timeout() {
+                return Err(Timeout::WaitCardReady.into());
+            }
+            self.delay_milli(2);
+        }
+    }
+
     pub fn check_rca(&self) -> Result<Rca, CardError> {
         self.delay_milli(10);
         let cmd = send_relative_address();
@@ -233,20 +567,39 @@ impl MmcOperate {
         Ok(rca)
     }
 
+    /// CMD3 for MMC: the host assigns the relative address
+    pub fn set_rca(&self, rca: u16) -> Result<Rca, CardError> {
+        self.delay_milli(10);
+        let status = self.send_cmd(set_relative_address(rca))?.card_status();
+        debug!("{:?}", status);
+        Ok(Rca::from(u32::from(rca) << 16))
+    }
+
     pub fn check_cid(&self) -> Result<Cid, CardError> {
         self.delay_milli(10);
         let cmd = all_send_cid();
         let cid = self.send_cmd(cmd)?.cid();
         debug!("{:?}", cid);
+        if !cid.crc_valid() {
+            error!("cid crc7 mismatch: {:02x?}", cid.raw());
+            return Err(CardError::RegisterCrc);
+        }
         Ok(cid)
     }
 
+    /// CMD9, asked again when the CRC7 shows a badly assembled response
     pub fn check_csd(&self, rca: Rca) -> Result<Csd, CardError> {
-        self.delay_milli(10);
-        let cmd = send_csd(rca.address());
-        let csd = self.send_cmd(cmd)?.csd();
-        debug!("{:?}", csd);
-        Ok(csd)
+        for _ in 0..CSD_RETRIES {
+            self.delay_milli(10);
+            let cmd = send_csd(rca.address());
+            let csd = self.send_cmd(cmd)?.csd();
+            debug!("{:?}", csd);
+            if csd.crc_valid() {
+                return Ok(csd);
+            }
+            error!("csd crc7 mismatch: {:02x?}", csd.raw());
+        }
+        Err(CardError::RegisterCrc)
     }
 
     pub fn sel_card(&self, rca: Rca) -> Result<(), CardError> {
@@ -257,14 +610,171 @@ impl MmcOperate {
         Ok(())
     }
 
-    pub fn function_switch(&self, arg: u32) -> Result<(), CardError> {
-        self.delay_milli(10);
-        let cmd = switch_function(arg);
+    pub fn card_status(&self, rca: Rca) -> Result<CardStatus, CardError> {
+        Ok(self.send_cmd(send_status(rca.address()))?.card_status())
+    }
+
+    /// Wait for the card to leave the programming state after an R1b command
+    pub fn wait_card_ready(&self, rca: Rca, millis: usize) -> Result<CardStatus, CardError> {
+        self.wait_for_data_line()?;
+        let timer = CountDown::new(millis, self.get_macros);
+        loop {
+            let status = self.card_status(rca)?;
+            if status.ready_for_data() && status.state() != CurrentState::Programming {
+                break Ok(status);
+            }
+            if timer.timeout() {
+                return Err(Timeout::WaitCardReady.into());
+            }
+            self.delay_macros(100);
+        }
+    }
+
+    /// CMD32/CMD33 (CMD35/CMD36 on eMMC) around the card addresses `start..=end`, then
+    /// CMD38 with `arg`, polling the busy DAT0 for up to `millis`
+    pub fn erase(
+        &self,
+        rca: Rca,
+        mmc: bool,
+        start: u32,
+        end: u32,
+        arg: u32,
+        millis: usize,
+    ) -> Result<(), CardError> {
+        let (first, last) = if mmc {
+            (erase_group_start(start), erase_group_end(end))
+        } else {
+            (erase_wr_blk_start(start), erase_wr_blk_end(end))
+        };
+        self.send_cmd(first)?;
+        self.send_cmd(last)?;
+        self.send_cmd(erase(arg))?;
+        if !self.wait_for(millis, || {
+            read_reg::<u32>(self.sdio_base, REG_STATUS) & StatusMask::data_busy.bits() == 0
+        }) {
+            return Err(Timeout::WaitDataLine.into());
+        }
+        // erase errors are reported in the response following CMD38
+        let status = self.card_status(rca)?;
+        if status.erase_seq_error()
+            || status.erase_param()
+            || status.wp_erase_skip()
+            || status.out_of_range()
+            || status.address_error()
+        {
+            error!("erase {start}..={end} failed: {:?}", status);
+            return Err(CardError::EraseFailed);
+        }
+        Ok(())
+    }
+
+    /// eMMC CMD6: write `value` to EXT_CSD byte `index` and wait for the card to apply it
+    pub fn mmc_switch(
+        &self,
+        rca: Rca,
+        index: u8,
+        value: u8,
+        millis: usize,
+    ) -> Result<(), CardError> {
+        self.send_switch(index, value)?;
+        self.check_switch(rca, index, value, millis)
+    }
+
+    /// eMMC CMD6 alone, for a switch after which the host changes its own timing
+    /// before it checks the outcome with `check_switch`
+    pub fn send_switch(&self, index: u8, value: u8) -> Result<(), CardError> {
+        let cmd = mmc_switch(SwitchAccess::WriteByte, index, value);
         let status = self.send_cmd(cmd)?.card_status();
         debug!("{:?}", status);
         Ok(())
     }
 
+    /// Wait up to `millis` for the card to apply a CMD6 and check it accepted it
+    pub fn check_switch(
+        &self,
+        rca: Rca,
+        index: u8,
+        value: u8,
+        millis: usize,
+    ) -> Result<(), CardError> {
+        let millis = if millis == 0 {
+            CARD_READY_TMOUT
+        } else {
+            millis
+        };
+        let status = self.wait_card_ready(rca, millis)?;
+        if status.switch_error() {
+            error!("switch ext_csd[{index}] = {value} failed");
+            return Err(CardError::SwitchFailed);
+        }
+        Ok(())
+    }
+
+    /// eMMC CMD8: read the 512 byte EXT_CSD
+    pub fn check_ext_csd(&self) -> Result<ExtCsd, CardError> {
+        let mut buf = [0u8; 512];
+        self.set_transfer_size(1, 512);
+        let status = self.send_cmd(send_ext_csd())?.card_status();
+        debug!("{:?}", status);
+        self.read_data(&mut buf)?;
+        let ext_csd = ExtCsd::from(buf);
+        debug!("{:?}", ext_csd);
+        Ok(ext_csd)
+    }
+
+    pub fn set_blk_len(&self, len: u32) -> Result<(), CardError> {
+        let status = self.send_cmd(set_block_len(len))?.card_status();
+        debug!("{:?}", status);
+        Ok(())
+    }
+
+    /// CMD6 in check or set mode, returns the switch status read from the data lines
+    pub fn function_switch(
+        &self,
+        set: bool,
+        group: SwitchGroup,
+        function: u8,
+    ) -> Result<SwitchStatus, CardError> {
+        let mut buf = [0u8; 64];
+        self.set_transfer_size(1, 64);
+        let cmd = switch_function(set, group, function);
+        let status = self.send_cmd(cmd)?.card_status();
+        debug!("{:?}", status);
+        self.read_data(&mut buf)?;
+        self.wait_for_data_line()?;
+        let status = SwitchStatus::from(buf);
+        debug!("{:?}", status);
+        Ok(status)
+    }
+
+    /// ACMD51: read the SD Configuration Register
+    pub fn check_scr(&self, rca: Rca) -> Result<Scr, CardError> {
+        let mut buf = [0u8; 8];
+        self.send_cmd(app_cmd(rca.address()))?;
+        self.set_transfer_size(1, 8);
+        let status = self.send_cmd(send_scr())?.card_status();
+        debug!("{:?}", status);
+        self.read_data(&mut buf)?;
+        self.wait_for_data_line()?;
+        let scr = Scr::from(buf);
+        debug!("{:?}", scr);
+        Ok(scr)
+    }
+
+    /// ACMD13: read the 64 byte SD Status
+    pub fn check_sd_status(&self, rca: Rca) -> Result<SdStatus, CardError> {
+        let mut buf = [0u8; 64];
+        self.send_cmd(app_cmd(rca.address()))?;
+        self.set_transfer_size(1, 64);
+        let status = self.send_cmd(sd_status())?.card_status();
+        debug!("{:?}", status);
+        self.read_data(&mut buf)?;
+        self.wait_for_data_line()?;
+        let sd_status = SdStatus::from(buf);
+        debug!("{:?}", sd_status);
+        Ok(sd_status)
+    }
+
     pub fn set_bus(&self, rca: Rca) -> Result<(), CardError> {
         self.delay_milli(10);
         self.send_cmd(app_cmd(rca.address()))?;
@@ -277,10 +787,10 @@ impl MmcOperate {
         let cmd = stop_transmission();
         loop {
             self.wait_for_cmd_line()?;
-            write_reg::<u32>(self.sdio_base, REG_RINTSTS, InterruptMask::all().bits());
+            self.clear_int(InterruptMask::all().bits());
             write_reg::<u32>(self.sdio_base, REG_CMDARG, cmd.arg());
             write_reg::<u32>(self.sdio_base, REG_CMD, cmd.cmd());
-            if read_reg::<u32>(self.sdio_base, REG_RINTSTS) & InterruptMask::hle.bits() == 0 {
+            if self.int_status() & InterruptMask::hle.bits() == 0 {
                 debug!("send {:?}", CmdMask::from_bits(cmd.cmd()).unwrap());
                 break;
             }
@@ -291,6 +801,11 @@ impl MmcOperate {
         Ok(())
     }
 
+    /// Deadline `millis` from now on the host clock
+    pub fn count_down(&self, millis: usize) -> CountDown {
+        CountDown::new(millis, self.get_macros)
+    }
+
     fn wait_for<F: FnMut() -> bool>(&self, millis: usize, mut f: F) -> bool {
         let count_down = CountDown::new(millis, self.get_macros);
         loop {
@@ -300,6 +815,7 @@ impl MmcOperate {
             if f() {
                 break;
             }
+            self.idle();
         }
         true
     }
@@ -311,7 +827,53 @@ impl MmcOperate {
     fn delay_macros(&self, macros: usize) {
         let deadline = macros + (self.get_macros)();
         while (self.get_macros)() < deadline {
-            core::hint::spin_loop();
+            self.idle();
+        }
+    }
+
+    /// Raw interrupt status, including the bits already acknowledged by the IRQ handler
+    pub fn int_status(&self) -> u32 {
+        let mask = read_reg::<u32>(self.sdio_base, REG_RINTSTS);
+        match self.irq {
+            Some(irq) => mask | irq.rintsts(),
+            None => mask,
+        }
+    }
+
+    /// Unmask exactly the FIFO watermark interrupts in `fifo`, the handler masks
+    /// them again once they fire
+    pub fn set_fifo_irq(&self, fifo: u32) {
+        let intmask = read_reg::<u32>(self.sdio_base, REG_INTMASK) & !INTMASK_FIFO;
+        write_reg::<u32>(self.sdio_base, REG_INTMASK, intmask | fifo & INTMASK_FIFO);
+    }
+
+    pub fn clear_int(&self, mask: u32) {
+        if let Some(irq) = self.irq {
+            irq.clear_rintsts(mask);
+        }
+        write_reg::<u32>(self.sdio_base, REG_RINTSTS, mask);
+    }
+
+    pub fn dma_status(&self) -> u32 {
+        let status = read_reg::<u32>(self.sdio_base, REG_IDSTS);
+        match self.irq {
+            Some(irq) => status | irq.idsts(),
+            None => status,
+        }
+    }
+
+    pub fn clear_dma(&self, mask: u32) {
+        if let Some(irq) = self.irq {
+            irq.clear_idsts(mask);
+        }
+        write_reg::<u32>(self.sdio_base, REG_IDSTS, mask);
+    }
+
+    /// Called while waiting on the controller, yields through the wait hook in interrupt mode
+    fn idle(&self) {
+        match self.irq {
+            Some(_) => (self.wait)(),
+            None => core::hint::spin_loop(),
         }
     }
 }
@@ -0,0 +1,81 @@
+use lego_device::{
+    BlkDevInfo, BlockDevice, BlockSize, Device, DeviceError, DeviceStatus, DeviceType,
+};
+use log::trace;
+
+use super::info::SdDevInfo;
+use super::mmc_reg::MmcPartition;
+use super::DwMmcHost;
+
+/// Block device on one eMMC hardware partition, borrowed from [`DwMmcHost::partition_dev`].
+pub struct MmcPartDev<'a> {
+    host: &'a DwMmcHost,
+    part: MmcPartition,
+    info: SdDevInfo,
+}
+
+impl<'a> MmcPartDev<'a> {
+    pub(crate) fn new(host: &'a DwMmcHost, part: MmcPartition, info: SdDevInfo) -> Self {
+        Self { host, part, info }
+    }
+
+    pub fn partition(&self) -> MmcPartition {
+        self.part
+    }
+
+    /// Transfers must stay inside the partition, the card would reject them anyway
+    fn check_range(&self, lba: usize, len: usize) -> Result<usize, DeviceError> {
+        let count = self.host.whole_blocks(len)?;
+        if (lba + count) as u64 > self.info.block_count() {
+            return Err(DeviceError::InvalidConfiguration);
+        }
+        Ok(count)
+    }
+}
+
+impl Device for MmcPartDev<'_> {
+    fn close(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn status(&self) -> DeviceStatus {
+        self.host.status()
+    }
+
+    fn reinit(&mut self) -> Result<(), DeviceError> {
+        // the host is shared with the other partitions, re-initialise it instead
+        Err(DeviceError::UnsupportedOperation)
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn error_handle(&self) -> DeviceStatus {
+        DeviceStatus::Error
+    }
+}
+
+impl BlockDevice for MmcPartDev<'_> {
+    fn read_block(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
+        trace!("{:?} read block: {}", self.part, lba);
+        let count = self.check_range(lba, buf.len())?;
+        self.host.select_partition(self.part)?;
+        self.host.read_part_blocks(lba, count, buf)
+    }
+
+    fn write_block(&self, lba: usize, data: &[u8]) -> Result<(), DeviceError> {
+        trace!("{:?} write block: {}", self.part, lba);
+        let count = self.check_range(lba, data.len())?;
+        self.host.select_partition(self.part)?;
+        self.host.write_part_blocks(lba, count, data)
+    }
+
+    fn block_size(&self) -> BlockSize {
+        BlockSize::Lb512
+    }
+
+    fn information(&self) -> &dyn BlkDevInfo {
+        &self.info
+    }
+}
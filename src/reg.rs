@@ -12,6 +12,7 @@ reg_map!(
     REG_BLKSIZ 0x01C,
     REG_BYTCNT 0x020,
     REG_INTMASK 0x024,
+    REG_MINTSTS 0x040,
     REG_RINTSTS 0x044,
     REG_CMDARG 0x028,
     REG_CMD 0x02C,
@@ -40,9 +41,57 @@ reg_map!(
     REG_DSCADDR 0x94,
     REG_BUFADDR 0x98,
     REG_CARD_THR_CTL 0x100,
+    REG_EMMC_DDR 0x10C,
     REG_DATA 0x200
 );
 pub const DATA_TMOUT_DEFUALT: usize = 0xFFFFFF << 8;
+/// Interrupts serviced when the host runs in interrupt mode. The FIFO watermark
+/// interrupts are only unmasked while a PIO request waits on them, see [`INTMASK_FIFO`].
+pub const INTMASK_DEFAULT: u32 = InterruptMask::cmd.bits()
+    | InterruptMask::dto.bits()
+    | InterruptMask::acd.bits()
+    | INTMASK_ERROR;
+/// FIFO watermark interrupts, they stay raised until the FIFO is drained or filled
+pub const INTMASK_FIFO: u32 = InterruptMask::txdr.bits() | InterruptMask::rxdr.bits();
+/// Command and data error interrupts
+pub const INTMASK_ERROR: u32 = InterruptMask::re.bits()
+    | InterruptMask::rcrc.bits()
+    | InterruptMask::dcrc.bits()
+    | InterruptMask::rto.bits()
+    | InterruptMask::drto.bits()
+    | InterruptMask::hto.bits()
+    | InterruptMask::frun.bits()
+    | InterruptMask::hle.bits()
+    | InterruptMask::sbe.bits()
+    | InterruptMask::ebe.bits();
+/// Milliseconds a card may stay busy powering up after ACMD41/CMD1
+pub const CARD_READY_TMOUT: usize = 1000;
+/// Milliseconds an async request waits for the command done or the next data
+/// interrupt before taking the interrupt as lost
+pub const IRQ_TMOUT: usize = 1000;
+/// Milliseconds a card may hold DAT0 busy after a write or a stop command
+pub const BUSY_TMOUT: usize = 10_000;
+/// CMD1 argument without the voltage window: sector addressing and 1.70-1.95V
+pub const MMC_OCR_ARG: u32 = 0x4000_0080;
+/// REG_CTYPE values for card 0
+pub const CTYPE_1BIT: u32 = 0;
+pub const CTYPE_4BIT: u32 = 0b1;
+pub const CTYPE_8BIT: u32 = 0b1 << 16;
+/// REG_UHS bits for card 0: 1.8V signaling and DDR sampling
+pub const UHS_VOLT_18: u32 = 0b1;
+pub const UHS_DDR: u32 = 0b1 << 16;
+/// REG_EMMC_DDR: HS400 sampling on the data strobe, on controllers that support HS400
+pub const EMMC_DDR_HS400: u32 = 0b1 << 31;
+/// CMD9 attempts before a CSD failing its CRC7 is given up on
+pub const CSD_RETRIES: usize = 3;
+/// Relative address the host gives to an MMC card
+pub const MMC_RCA: u16 = 1;
+/// IP version in the low half of REG_VERID, e.g. 0x270a for 2.70a
+pub const VERID_VERSION: u32 = 0xFFFF;
+/// First IP version taken to raise the busy clear interrupt of CARDTHRCTL
+pub const VERID_BUSY_CLR_INT: u32 = 0x280a;
+/// Write-1-to-clear bits of REG_IDSTS
+pub const IDSTS_CLEAR: u32 = 0x337;
 // pub const BLKSIZ_DEFAULT: usize = 0x200;
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -124,6 +173,14 @@ bitflags! {
         const fifo_rx_watermark= 0b1;
     }
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CardThrCtl: u32{
+        const card_threshold = 0xFFF << 16;
+        const card_wr_thr_en = 0b1 << 2;
+        const busy_clr_int_en = 0b1 << 1;
+        const card_rd_thr_en = 0b1;
+    }
+
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct HardConfig: u32{
         const card_type = 0x1;
@@ -138,7 +195,17 @@ bitflags! {
         const set_clk_false_path = 0b1 <<23;
         const num_clk_div_sub1 = 0b11<<24;
         const area_optimized = 0b1 << 26;
-        const fifo_depth = 0x1F << 27;
+        /// IDMAC with 64-bit addresses, 31:28 are reserved
+        const addr_config = 0b1 << 27;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BusMode: u32{
+        const swr = 0b1;
+        const fb = 0b1 << 1;
+        const dsl = 0x1F << 2;
+        const de = 0b1 << 7;
+        const pbl = 0b111 << 8;
     }
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -166,6 +233,83 @@ bitflags! {
     }
 }
 
+/// Width of a single REG_DATA FIFO access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoWidth {
+    B16,
+    B32,
+    B64,
+}
+
+impl FifoWidth {
+    pub fn bytes(&self) -> usize {
+        match self {
+            FifoWidth::B16 => 2,
+            FifoWidth::B32 => 4,
+            FifoWidth::B64 => 8,
+        }
+    }
+}
+
+/// Burst sizes selectable by FIFOTH DW_DMA_Multiple_Transaction_Size
+const MSIZE: [u32; 8] = [1, 4, 8, 16, 32, 64, 128, 256];
+
+/// Value of REG_FIFOTH: DMA burst size and the RX/TX watermarks in FIFO words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FifoThreshold {
+    msize: u32,
+    rx_wmark: u32,
+    tx_wmark: u32,
+}
+
+impl FifoThreshold {
+    /// Half full watermarks with single transfers, used until the block size is known
+    pub fn new(depth: u32) -> Self {
+        Self {
+            msize: 0,
+            rx_wmark: (depth / 2).saturating_sub(1),
+            tx_wmark: depth / 2,
+        }
+    }
+
+    /// Pick the largest burst that divides both the block and the TX watermark space,
+    /// and raise RXDR once a whole burst is buffered.
+    pub fn for_block(depth: u32, width: FifoWidth, blk_sz: u32) -> Self {
+        let mut th = Self::new(depth);
+        let width = width.bytes() as u32;
+        if !blk_sz.is_multiple_of(width) {
+            th.rx_wmark = 1;
+            return th;
+        }
+        let blk_words = blk_sz / width;
+        let tx_space = depth - th.tx_wmark;
+        for idx in (1..MSIZE.len()).rev() {
+            if blk_words.is_multiple_of(MSIZE[idx]) && tx_space.is_multiple_of(MSIZE[idx]) {
+                th.msize = idx as u32;
+                th.rx_wmark = MSIZE[idx] - 1;
+                break;
+            }
+        }
+        th
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self {
+            msize: (bits >> 28) & 0x7,
+            rx_wmark: (bits >> 16) & 0xFFF,
+            tx_wmark: bits & 0xFFF,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.msize << 28 | self.rx_wmark << 16 | self.tx_wmark
+    }
+
+    pub fn rx_wmark(&self) -> u32 {
+        self.rx_wmark
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct HardConf(pub u32);
 impl From<u32> for HardConf {
@@ -174,6 +318,22 @@ impl From<u32> for HardConf {
     }
 }
 
+impl HardConf {
+    pub fn fifo_width(&self) -> FifoWidth {
+        match (self.0 & HardConfig::h_data_width.bits()) >> 7 {
+            0 => FifoWidth::B16,
+            2 => FifoWidth::B64,
+            // 1 is 32 bit, the remaining encodings are reserved
+            _ => FifoWidth::B32,
+        }
+    }
+
+    /// 0 means no external DMA interface, i.e. the internal DMAC is used
+    pub fn dma_interface(&self) -> u32 {
+        (self.0 & HardConfig::dma_interface.bits()) >> 16
+    }
+}
+
 impl Debug for HardConf {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut f = f.debug_map();
@@ -267,9 +427,29 @@ impl Debug for HardConf {
             f.entry(&HardConfig::area_optimized, &"no area optimization");
         }
         f.entry(
-            &HardConfig::fifo_depth,
-            &((conf & HardConfig::fifo_depth.bits()) >> 27),
+            &HardConfig::addr_config,
+            &((conf & HardConfig::addr_config.bits()) >> 27),
         );
         f.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifoth_for_512_byte_blocks() {
+        // the largest burst dividing both the 128 word block and the TX watermark space
+        let fifoth = |depth| FifoThreshold::for_block(depth, FifoWidth::B32, 512).bits();
+        assert_eq!(fifoth(16), 0x2007_0008);
+        assert_eq!(fifoth(32), 0x300F_0010);
+        assert_eq!(fifoth(256), 0x607F_0080);
+    }
+
+    #[test]
+    fn fifoth_for_partial_words() {
+        let fifoth = FifoThreshold::for_block(32, FifoWidth::B32, 6);
+        assert_eq!(fifoth.bits(), 0x0001_0010);
+    }
+}
@@ -0,0 +1,329 @@
+use core::slice;
+
+use lego_device::DeviceError;
+use log::{debug, warn};
+
+use super::cmd::{read_multiple_block, set_block_count, write_multiple_block};
+use super::err::{CardError, Rpmb};
+use super::mmc_reg::MmcPartition;
+use super::DwMmcHost;
+
+/// Size of an RPMB data frame, every RPMB access moves whole frames
+pub const RPMB_FRAME_SIZE: usize = 512;
+/// Payload carried by one frame, RPMB addresses count in these half sectors
+pub const RPMB_DATA_SIZE: usize = 256;
+/// Frames read back per authenticated read request
+const RPMB_READ_FRAMES: usize = 4;
+
+// Frame field offsets, Ref JESD84-B51 Table 18. Multi byte fields are big endian.
+const RPMB_KEY_MAC: usize = 196;
+const RPMB_DATA: usize = 228;
+const RPMB_NONCE: usize = 484;
+const RPMB_WRITE_COUNTER: usize = 500;
+const RPMB_ADDRESS: usize = 504;
+const RPMB_BLOCK_COUNT: usize = 506;
+const RPMB_RESULT: usize = 508;
+const RPMB_REQ_RESP: usize = 510;
+
+/// Request message types, the card answers with the same value shifted into the high byte
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RpmbRequest {
+    ProgramKey = 1,
+    ReadCounter = 2,
+    Write = 3,
+    Read = 4,
+    ResultRead = 5,
+}
+
+impl RpmbRequest {
+    fn response(self) -> u16 {
+        (self as u16) << 8
+    }
+}
+
+/// Authentication primitives of the RPMB, provided by the platform.
+///
+/// The driver never holds the authentication key: frames are signed and
+/// verified through this trait, so the key may stay in a secure element or TEE.
+pub trait RpmbMac {
+    /// HMAC-SHA256 keyed with the RPMB authentication key over the concatenation of `data`
+    fn hmac_sha256(&self, data: &[&[u8]]) -> [u8; 32];
+
+    /// Fresh random nonce for counter reads and authenticated reads
+    fn nonce(&self) -> [u8; 16];
+}
+
+/// One 512 byte RPMB data frame
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct RpmbFrame([u8; RPMB_FRAME_SIZE]);
+
+impl RpmbFrame {
+    pub const fn new() -> Self {
+        Self([0; RPMB_FRAME_SIZE])
+    }
+
+    fn request(req: RpmbRequest) -> Self {
+        let mut frame = Self::new();
+        frame.set_u16(RPMB_REQ_RESP, req as u16);
+        frame
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.0[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn key_mac(&self) -> &[u8] {
+        &self.0[RPMB_KEY_MAC..RPMB_DATA]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.0[RPMB_DATA..RPMB_NONCE]
+    }
+
+    pub fn nonce(&self) -> &[u8] {
+        &self.0[RPMB_NONCE..RPMB_WRITE_COUNTER]
+    }
+
+    pub fn write_counter(&self) -> u32 {
+        u32::from_be_bytes([
+            self.0[RPMB_WRITE_COUNTER],
+            self.0[RPMB_WRITE_COUNTER + 1],
+            self.0[RPMB_WRITE_COUNTER + 2],
+            self.0[RPMB_WRITE_COUNTER + 3],
+        ])
+    }
+
+    pub fn address(&self) -> u16 {
+        self.u16_at(RPMB_ADDRESS)
+    }
+
+    pub fn block_count(&self) -> u16 {
+        self.u16_at(RPMB_BLOCK_COUNT)
+    }
+
+    pub fn result(&self) -> u16 {
+        self.u16_at(RPMB_RESULT)
+    }
+
+    pub fn req_resp(&self) -> u16 {
+        self.u16_at(RPMB_REQ_RESP)
+    }
+
+    /// Bytes covered by the MAC: data up to the request/response type
+    pub fn mac_data(&self) -> &[u8] {
+        &self.0[RPMB_DATA..]
+    }
+
+    fn check_response(&self, req: RpmbRequest) -> Result<(), Rpmb> {
+        if self.req_resp() != req.response() {
+            return Err(Rpmb::ResponseType(self.req_resp()));
+        }
+        Rpmb::check(self.result())?;
+        if self.result() & 0x80 != 0 {
+            warn!("rpmb write counter expired");
+        }
+        Ok(())
+    }
+}
+
+impl Default for RpmbFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn frames_bytes(frames: &[RpmbFrame]) -> &[u8] {
+    // SAFETY: RpmbFrame is a transparent byte array, so the frames are contiguous bytes
+    unsafe { slice::from_raw_parts(frames.as_ptr() as *const u8, size_of_val(frames)) }
+}
+
+fn frames_bytes_mut(frames: &mut [RpmbFrame]) -> &mut [u8] {
+    // SAFETY: as above, and every byte pattern is a valid frame
+    unsafe { slice::from_raw_parts_mut(frames.as_mut_ptr() as *mut u8, size_of_val(frames)) }
+}
+
+/// Replay Protected Memory Block of an eMMC, borrowed from [`DwMmcHost::rpmb`].
+///
+/// Addresses and lengths count in 256 byte half sectors. Each request switches
+/// the card to the RPMB partition first; the next user or partition transfer switches back.
+pub struct MmcRpmb<'a, M: RpmbMac> {
+    host: &'a DwMmcHost,
+    mac: M,
+}
+
+impl<'a, M: RpmbMac> MmcRpmb<'a, M> {
+    pub(crate) fn new(host: &'a DwMmcHost, mac: M) -> Self {
+        Self { host, mac }
+    }
+
+    /// Size of the RPMB partition in bytes
+    pub fn size(&self) -> u64 {
+        self.host.partition_size(MmcPartition::Rpmb)
+    }
+
+    /// Program the authentication key. This is a one time operation on the card,
+    /// a second attempt fails with a general failure.
+    pub fn program_key(&self, key: &[u8; 32]) -> Result<(), CardError> {
+        let mut frame = RpmbFrame::request(RpmbRequest::ProgramKey);
+        frame.0[RPMB_KEY_MAC..RPMB_DATA].copy_from_slice(key);
+        self.send_frames(&[frame], true)?;
+        let resp = self.result_read()?;
+        resp.check_response(RpmbRequest::ProgramKey)?;
+        Ok(())
+    }
+
+    /// Read the authenticated write counter
+    pub fn read_counter(&self) -> Result<u32, CardError> {
+        let nonce = self.mac.nonce();
+        let mut frame = RpmbFrame::request(RpmbRequest::ReadCounter);
+        frame.0[RPMB_NONCE..RPMB_WRITE_COUNTER].copy_from_slice(&nonce);
+        self.send_frames(&[frame], false)?;
+        let mut resp = [RpmbFrame::new()];
+        self.recv_frames(&mut resp)?;
+        resp[0].check_response(RpmbRequest::ReadCounter)?;
+        self.verify(&resp, Some(&nonce))?;
+        debug!("rpmb write counter: {}", resp[0].write_counter());
+        Ok(resp[0].write_counter())
+    }
+
+    /// Authenticated read of `buf.len() / 256` half sectors starting at `addr`
+    pub fn read(&self, addr: u16, buf: &mut [u8]) -> Result<(), CardError> {
+        self.check_range(addr, buf.len())?;
+        let mut addr = addr;
+        for chunk in buf.chunks_mut(RPMB_READ_FRAMES * RPMB_DATA_SIZE) {
+            let count = chunk.len() / RPMB_DATA_SIZE;
+            let nonce = self.mac.nonce();
+            let mut frame = RpmbFrame::request(RpmbRequest::Read);
+            frame.0[RPMB_NONCE..RPMB_WRITE_COUNTER].copy_from_slice(&nonce);
+            frame.set_u16(RPMB_ADDRESS, addr);
+            self.send_frames(&[frame], false)?;
+            let mut resp = [RpmbFrame::new(); RPMB_READ_FRAMES];
+            let resp = &mut resp[..count];
+            self.recv_frames(resp)?;
+            resp[count - 1].check_response(RpmbRequest::Read)?;
+            self.verify(resp, Some(&nonce))?;
+            for (data, frame) in chunk.chunks_mut(RPMB_DATA_SIZE).zip(resp.iter()) {
+                data.copy_from_slice(frame.data());
+            }
+            // wraps past 0xFFFF only once the last half sector is read
+            addr = addr.wrapping_add(count as u16);
+        }
+        Ok(())
+    }
+
+    /// Authenticated write of `data.len() / 256` half sectors starting at `addr`,
+    /// one reliable write per half sector. Returns the write counter after the last one.
+    pub fn write(&self, addr: u16, data: &[u8]) -> Result<u32, CardError> {
+        self.check_range(addr, data.len())?;
+        let mut counter = self.read_counter()?;
+        for (i, chunk) in data.chunks(RPMB_DATA_SIZE).enumerate() {
+            let mut frame = RpmbFrame::request(RpmbRequest::Write);
+            frame.0[RPMB_DATA..RPMB_NONCE].copy_from_slice(chunk);
+            frame.0[RPMB_WRITE_COUNTER..RPMB_ADDRESS].copy_from_slice(&counter.to_be_bytes());
+            frame.set_u16(RPMB_ADDRESS, addr.wrapping_add(i as u16));
+            frame.set_u16(RPMB_BLOCK_COUNT, 1);
+            let mac = self.mac.hmac_sha256(&[frame.mac_data()]);
+            frame.0[RPMB_KEY_MAC..RPMB_DATA].copy_from_slice(&mac);
+            self.send_frames(&[frame], true)?;
+            let resp = self.result_read()?;
+            resp.check_response(RpmbRequest::Write)?;
+            self.verify(slice::from_ref(&resp), None)?;
+            if resp.write_counter() != counter.wrapping_add(1) {
+                return Err(Rpmb::CounterMismatch.into());
+            }
+            counter = resp.write_counter();
+        }
+        Ok(counter)
+    }
+
+    /// `len` bytes of whole half sectors from `addr` on stay inside the partition
+    fn check_range(&self, addr: u16, len: usize) -> Result<(), CardError> {
+        if !len.is_multiple_of(RPMB_DATA_SIZE) {
+            return Err(CardError::BufferSize);
+        }
+        let end = usize::from(addr) + len / RPMB_DATA_SIZE;
+        if end as u64 > self.size() / RPMB_DATA_SIZE as u64 {
+            return Err(CardError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Fetch the response of the previous write or key programming request
+    fn result_read(&self) -> Result<RpmbFrame, CardError> {
+        self.send_frames(&[RpmbFrame::request(RpmbRequest::ResultRead)], false)?;
+        let mut resp = [RpmbFrame::new()];
+        self.recv_frames(&mut resp)?;
+        Ok(resp[0])
+    }
+
+    /// Check the MAC of the last response frame over all of them, and the nonce
+    /// echoed back by counter reads and data reads
+    fn verify(&self, frames: &[RpmbFrame], nonce: Option<&[u8; 16]>) -> Result<(), Rpmb> {
+        let mut data = [&[][..]; RPMB_READ_FRAMES];
+        for (d, frame) in data.iter_mut().zip(frames.iter()) {
+            *d = frame.mac_data();
+        }
+        let last = &frames[frames.len() - 1];
+        if self.mac.hmac_sha256(&data[..frames.len()])[..] != *last.key_mac() {
+            return Err(Rpmb::MacMismatch);
+        }
+        match nonce {
+            Some(nonce) if last.nonce() != nonce => Err(Rpmb::NonceMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// CMD23 + CMD25 carrying `frames`
+    fn send_frames(&self, frames: &[RpmbFrame], reliable: bool) -> Result<(), CardError> {
+        let opt = &self.host.mmc_opt;
+        self.host.select_partition(MmcPartition::Rpmb)?;
+        opt.send_cmd(set_block_count(frames.len() as u16, reliable))?;
+        opt.set_transfer_size(frames.len() as u32, RPMB_FRAME_SIZE as u32);
+        let ret = opt
+            .send_cmd(write_multiple_block(0, false))
+            .and_then(|_| opt.write_data(frames_bytes(frames)));
+        self.finish(ret)
+    }
+
+    /// CMD23 + CMD18 filling `frames`
+    fn recv_frames(&self, frames: &mut [RpmbFrame]) -> Result<(), CardError> {
+        let opt = &self.host.mmc_opt;
+        opt.send_cmd(set_block_count(frames.len() as u16, false))?;
+        opt.set_transfer_size(frames.len() as u32, RPMB_FRAME_SIZE as u32);
+        let ret = opt
+            .send_cmd(read_multiple_block(0, false))
+            .and_then(|_| opt.read_data(frames_bytes_mut(frames)));
+        self.finish(ret)
+    }
+
+    /// CMD23 ends the transfer by itself, CMD12 is only needed to abort it
+    fn finish(&self, ret: Result<(), CardError>) -> Result<(), CardError> {
+        let opt = &self.host.mmc_opt;
+        match ret {
+            Ok(_) => {
+                opt.wait_for_data_line()?;
+                Ok(())
+            }
+            Err(err) => {
+                debug!("{err:?}");
+                opt.stop_transmission_ops()?;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl DwMmcHost {
+    /// RPMB access on an eMMC, frames are authenticated through `mac`
+    pub fn rpmb<M: RpmbMac>(&self, mac: M) -> Result<MmcRpmb<'_, M>, DeviceError> {
+        if self.partition_size(MmcPartition::Rpmb) == 0 {
+            return Err(DeviceError::UnsupportedOperation);
+        }
+        Ok(MmcRpmb::new(self, mac))
+    }
+}
@@ -1,5 +1,7 @@
 use core::{fmt::Debug, str};
 
+use super::crc::crc7;
+
 #[non_exhaustive]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SDSpecVersion {
@@ -14,6 +16,27 @@ pub enum SDSpecVersion {
     Unknown,
 }
 
+/// Card family found by the identification sequence
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CardType {
+    Unknown,
+    /// Standard capacity, SD 1.x: no answer to CMD8
+    SdscV1,
+    /// Standard capacity, SD 2.0 or later
+    SdscV2,
+    /// High or extended capacity, block addressed
+    SdhcXc,
+    /// Ultra capacity, CSD version 3
+    Sduc,
+    Mmc,
+}
+
+impl CardType {
+    pub fn is_sd(&self) -> bool {
+        !matches!(self, CardType::Unknown | CardType::Mmc)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(unused)]
 pub enum BusWidth {
@@ -155,7 +178,7 @@ impl Ocr {
     pub const fn new() -> Self {
         Self(0)
     }
-    pub fn is_busy(&self) -> bool {
+    pub(crate) fn is_busy(&self) -> bool {
         self.0 & 0x8000_0000 == 0
     }
     pub fn voltage_window_mv(&self) -> Option<(u16, u16)> {
@@ -194,6 +217,10 @@ impl Ocr {
     pub fn high_capacity(&self) -> bool {
         self.0 & 0x4000_0000 != 0
     }
+
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
 }
 
 impl Debug for Ocr {
@@ -255,11 +282,15 @@ impl Cid {
     pub fn manufacturer_id(&self) -> u8 {
         self.bytes[0]
     }
-    #[allow(unused)]
     pub fn crc7(&self) -> u8 {
         (self.bytes[15] >> 1) & 0x7F
     }
 
+    /// The CRC7 sent by the card matches the other 15 bytes
+    pub fn crc_valid(&self) -> bool {
+        crc7(&self.bytes[..15]) == self.crc7()
+    }
+
     pub fn oem_id(&self) -> &str {
         str::from_utf8(&self.bytes[1..3]).unwrap_or("<ERR>")
     }
@@ -282,6 +313,40 @@ impl Cid {
             ((self.inner >> 12) as u16 & 0xFF) + 2000, // Year
         )
     }
+
+    /// MMC OID, a single byte where SD has two ASCII characters
+    pub fn mmc_oem_id(&self) -> u8 {
+        self.bytes[2]
+    }
+
+    /// MMC PNM, six characters where SD has five
+    pub fn mmc_product_name(&self) -> &str {
+        str::from_utf8(&self.bytes[3..9]).unwrap_or("<ERR>")
+    }
+
+    pub fn mmc_product_revision(&self) -> u8 {
+        self.bytes[9]
+    }
+
+    pub fn mmc_serial(&self) -> u32 {
+        (self.inner >> 16) as u32
+    }
+
+    /// MMC MDT counts years from 1997, from 2013 on cards of EXT_CSD revision 5 and later
+    pub fn mmc_manufacturing_date(&self, ext_csd_rev: u8) -> (u8, u16) {
+        let month = self.bytes[14] >> 4;
+        let year = u16::from(self.bytes[14] & 0xF) + 1997;
+        if ext_csd_rev > 4 && year < 2010 {
+            (month, year + 16)
+        } else {
+            (month, year)
+        }
+    }
+
+    /// The 16 CID bytes as sent by the card, CRC7 last
+    pub fn raw(&self) -> [u8; 16] {
+        self.bytes
+    }
 }
 
 impl Debug for Cid {
@@ -320,6 +385,31 @@ impl Csd {
         (self.0 >> 96) as u8
     }
 
+    /// TRAN_SPEED decoded to Hz, the card clock limit at default speed
+    pub fn max_transfer_clock(&self) -> u32 {
+        // Ref PLSS_v7_10 Table 5-6, time values are scaled by 10
+        const UNIT: [u32; 4] = [10_000, 100_000, 1_000_000, 10_000_000];
+        const VALUE: [u32; 16] = [
+            0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+        ];
+        let rate = self.transfer_rate();
+        let unit = usize::from(rate & 0x7);
+        if unit >= UNIT.len() {
+            return 0;
+        }
+        UNIT[unit] * VALUE[usize::from(rate >> 3) & 0xF]
+    }
+
+    /// CCC: one bit per supported command class
+    pub fn command_classes(&self) -> u16 {
+        (self.0 >> 84) as u16 & 0xFFF
+    }
+
+    /// Class 10 covers CMD6 SWITCH_FUNC, mandatory from SD 1.10 on
+    pub fn supports_switch(&self) -> bool {
+        self.command_classes() & (1 << 10) != 0
+    }
+
     pub fn block_length(&self) -> BlockSize {
         // Read block length
         match (self.0 >> 80) & 0xF {
@@ -379,23 +469,65 @@ impl Csd {
         }
     }
 
+    /// MMC SPEC_VERS, EXT_CSD exists from version 4 on
+    pub fn mmc_spec_version(&self) -> u8 {
+        (self.0 >> 122) as u8 & 0xF
+    }
+
+    /// MMC block count from C_SIZE, devices above 2GB report theirs in EXT_CSD
+    pub fn mmc_block_count(&self) -> u64 {
+        let c_size: u16 = ((self.0 >> 62) as u16) & 0xFFF;
+        let c_size_mult: u8 = ((self.0 >> 47) as u8) & 7;
+
+        ((c_size + 1) as u64) * ((1 << (c_size_mult + 2)) as u64)
+    }
+
     pub fn card_size(&self) -> u64 {
         let block_size_bytes = 1 << self.block_length() as u64;
 
         self.block_count() * block_size_bytes
     }
 
+    /// PERM_WRITE_PROTECT, the whole card is read only for good
+    pub fn perm_write_protect(&self) -> bool {
+        (self.0 >> 13) & 1 == 1
+    }
+
+    /// TMP_WRITE_PROTECT, the whole card is read only until the bit is cleared
+    pub fn tmp_write_protect(&self) -> bool {
+        (self.0 >> 12) & 1 == 1
+    }
+
+    /// SD erase unit in write blocks: one with ERASE_BLK_EN, else SECTOR_SIZE + 1
     pub fn erase_size_blocks(&self) -> u32 {
         if (self.0 >> 46) & 1 == 1 {
             // ERASE_BLK_EN
             1
         } else {
-            let sector_size_tens = (self.0 >> 43) & 0x7;
-            let sector_size_units = (self.0 >> 39) & 0xF;
-
-            (sector_size_tens as u32 * 10) + (sector_size_units as u32)
+            ((self.0 >> 39) & 0x7F) as u32 + 1
         }
     }
+
+    /// The 16 CSD bytes as sent by the card, CRC7 last
+    pub fn raw(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn crc7(&self) -> u8 {
+        (self.0 >> 1) as u8 & 0x7F
+    }
+
+    /// The CRC7 sent by the card matches the other 15 bytes
+    pub fn crc_valid(&self) -> bool {
+        crc7(&self.raw()[..15]) == self.crc7()
+    }
+
+    /// MMC erase group in write blocks, (ERASE_GRP_SIZE + 1) x (ERASE_GRP_MULT + 1)
+    pub fn mmc_erase_group_blocks(&self) -> u32 {
+        let size = ((self.0 >> 42) & 0x1F) as u32;
+        let mult = ((self.0 >> 37) & 0x1F) as u32;
+        (size + 1) * (mult + 1)
+    }
 }
 
 impl Debug for Csd {
@@ -409,6 +541,8 @@ impl Debug for Csd {
             .field("Read I (@max VDD)", &self.read_current_maximum_vdd())
             .field("Write I (@max VDD)", &self.write_current_maximum_vdd())
             .field("Erase Size (Blocks)", &self.erase_size_blocks())
+            .field("Perm Write Protect", &self.perm_write_protect())
+            .field("Tmp Write Protect", &self.tmp_write_protect())
             .finish()
     }
 }
@@ -508,6 +642,11 @@ impl CardStatus {
     pub fn app_cmd(&self) -> bool {
         self.0 & 0x20 != 0
     }
+
+    /// eMMC only: the last SWITCH was refused
+    pub fn switch_error(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
 }
 impl Debug for CardStatus {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -578,6 +717,13 @@ impl From<(u32, u32)> for Scr {
     }
 }
 
+/// ACMD51 data, most significant byte first
+impl From<[u8; 8]> for Scr {
+    fn from(value: [u8; 8]) -> Self {
+        Self(u64::from_be_bytes(value))
+    }
+}
+
 impl Scr {
     pub fn version(&self) -> SDSpecVersion {
         let spec = (self.0 >> 56) & 0xF;
@@ -599,7 +745,6 @@ impl Scr {
         }
     }
 
-    #[allow(unused)]
     pub fn bus_widths(&self) -> u8 {
         // Ref PLSS_v7_10 Table 5-21
         ((self.0 >> 48) as u8) & 0xF
@@ -612,6 +757,11 @@ impl Scr {
     pub fn bus_width_four(&self) -> bool {
         (self.0 >> 50) & 1 != 0
     }
+
+    /// The 8 SCR bytes as sent by the card
+    pub fn raw(&self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
 }
 
 impl Debug for Scr {
@@ -623,6 +773,122 @@ impl Debug for Scr {
             .finish()
     }
 }
+/// CMD6 function groups, group 5 and 6 are reserved
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SwitchGroup {
+    AccessMode = 1,
+    CommandSystem = 2,
+    DriverStrength = 3,
+    PowerLimit = 4,
+}
+
+/// Functions of the access mode group
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AccessMode {
+    /// Default speed, 25MHz
+    Sdr12 = 0,
+    /// High speed, 50MHz
+    Sdr25 = 1,
+    Sdr50 = 2,
+    Sdr104 = 3,
+    Ddr50 = 4,
+}
+
+/// 512 bit status returned on the data lines by CMD6, Ref PLSS_v7_10 Table 4-13
+#[derive(Copy, Clone)]
+pub struct SwitchStatus([u8; 64]);
+
+impl From<[u8; 64]> for SwitchStatus {
+    fn from(value: [u8; 64]) -> Self {
+        Self(value)
+    }
+}
+
+impl SwitchStatus {
+    /// Maximum current consumption in mA of the selected functions, zero on error
+    pub fn max_current(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]])
+    }
+
+    /// One bit per function the card supports in `group`
+    pub fn supported(&self, group: SwitchGroup) -> u16 {
+        let offset = 14 - 2 * group as usize;
+        u16::from_be_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    pub fn is_supported(&self, group: SwitchGroup, function: u8) -> bool {
+        self.supported(group) & (1 << function) != 0
+    }
+
+    /// Function selected in `group`: the one that would be switched to in check mode,
+    /// the one switched to in set mode. 0xF if it cannot be switched.
+    pub fn function(&self, group: SwitchGroup) -> u8 {
+        let byte = self.0[16 - (group as usize - 1) / 2];
+        if group as usize % 2 == 1 {
+            byte & 0xF
+        } else {
+            byte >> 4
+        }
+    }
+
+    /// Set mode result: `group` now runs `function`
+    pub fn switched(&self, group: SwitchGroup, function: u8) -> bool {
+        self.function(group) == function
+    }
+
+    /// 0: only supported and function fields are valid, 1: busy status too
+    pub fn version(&self) -> u8 {
+        self.0[17]
+    }
+
+    /// One bit per function of `group` that is still busy switching
+    pub fn busy(&self, group: SwitchGroup) -> u16 {
+        if self.version() == 0 {
+            return 0;
+        }
+        let offset = 30 - 2 * group as usize;
+        u16::from_be_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    pub fn raw(&self) -> [u8; 64] {
+        self.0
+    }
+}
+
+impl Debug for SwitchStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Switch Function Status")
+            .field("Max Current (mA)", &self.max_current())
+            .field("Access Mode", &self.supported(SwitchGroup::AccessMode))
+            .field(
+                "Command System",
+                &self.supported(SwitchGroup::CommandSystem),
+            )
+            .field(
+                "Driver Strength",
+                &self.supported(SwitchGroup::DriverStrength),
+            )
+            .field("Power Limit", &self.supported(SwitchGroup::PowerLimit))
+            .field(
+                "Access Mode Function",
+                &self.function(SwitchGroup::AccessMode),
+            )
+            .field(
+                "Command System Function",
+                &self.function(SwitchGroup::CommandSystem),
+            )
+            .field(
+                "Driver Strength Function",
+                &self.function(SwitchGroup::DriverStrength),
+            )
+            .field(
+                "Power Limit Function",
+                &self.function(SwitchGroup::PowerLimit),
+            )
+            .finish()
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct Cic(u32);
 
@@ -656,6 +922,17 @@ impl From<[u32; 16]> for SdStatus {
     }
 }
 
+/// ACMD13 data, bits 511:504 first
+impl From<[u8; 64]> for SdStatus {
+    fn from(value: [u8; 64]) -> Self {
+        let mut inner = [0u32; 16];
+        for (i, word) in value.chunks_exact(4).enumerate() {
+            inner[15 - i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        Self { inner }
+    }
+}
+
 impl SdStatus {
     pub fn bus_width(&self) -> BusWidth {
         match (self.inner[15] >> 30) & 3 {
@@ -689,24 +966,53 @@ impl SdStatus {
         (self.inner[13] >> 12) as u8 & 0xF
     }
 
+    /// AU size in 512 byte blocks, zero if undefined
+    pub fn allocation_unit_blocks(&self) -> u32 {
+        // Ref PLSS_v7_10 Table 4-47, in KB
+        const AU_KB: [u32; 16] = [
+            0, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 12288, 16384, 24576, 32768, 65536,
+        ];
+        AU_KB[usize::from(self.allocation_unit_size())] * 2
+    }
+
+    /// Number of AUs erased at once in `erase_timeout` seconds, zero if unspecified
     pub fn erase_size(&self) -> u16 {
-        (self.inner[13] & 0xFF) as u16 | ((self.inner[12] >> 24) & 0xFF) as u16
+        ((self.inner[13] & 0xFF) << 8) as u16 | ((self.inner[12] >> 24) & 0xFF) as u16
     }
 
     pub fn erase_timeout(&self) -> u8 {
         (self.inner[12] >> 18) as u8 & 0x3F
     }
 
+    /// Seconds added to every erase timeout
+    pub fn erase_offset(&self) -> u8 {
+        (self.inner[12] >> 16) as u8 & 0x3
+    }
+
     pub fn video_speed_class(&self) -> u8 {
-        (self.inner[11] & 0xFF) as u8
+        (self.inner[12] & 0xFF) as u8
     }
 
     pub fn app_perf_class(&self) -> u8 {
-        (self.inner[9] >> 16) as u8 & 0xF
+        (self.inner[10] >> 16) as u8 & 0xF
     }
 
     pub fn discard_support(&self) -> bool {
-        self.inner[8] & 0x0200_0000 != 0
+        self.inner[9] & 0x0200_0000 != 0
+    }
+
+    /// Full user area logical erase
+    pub fn fule_support(&self) -> bool {
+        self.inner[9] & 0x0100_0000 != 0
+    }
+
+    /// The 64 SD Status bytes as sent by the card
+    pub fn raw(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        for (i, word) in bytes.chunks_exact_mut(4).enumerate() {
+            word.copy_from_slice(&self.inner[15 - i].to_be_bytes());
+        }
+        bytes
     }
 }
 impl Debug for SdStatus {
@@ -723,7 +1029,9 @@ impl Debug for SdStatus {
             .field("AU Size", &self.allocation_unit_size())
             .field("Erase Size (units of AU)", &self.erase_size())
             .field("Erase Timeout (s)", &self.erase_timeout())
+            .field("Erase Offset (s)", &self.erase_offset())
             .field("Discard Support", &self.discard_support())
+            .field("FULE Support", &self.fule_support())
             .finish()
     }
 }
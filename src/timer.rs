@@ -12,6 +12,11 @@ impl CountDown {
         }
     }
 
+    /// Microseconds on the `get_macros` clock at which `timeout` turns true
+    pub fn deadline(&self) -> usize {
+        self.deadline
+    }
+
     pub fn timeout(&self) -> bool {
         (self.get_macros)() > self.deadline
     }
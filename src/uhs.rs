@@ -0,0 +1,208 @@
+use log::{debug, info, warn};
+
+use super::cmd::{send_tuning_block, send_tuning_block_hs200, Command};
+use super::err::CardError;
+use super::reg::*;
+use super::sd_reg::{AccessMode, BusWidth, SwitchGroup};
+use super::DwMmcHost;
+
+/// I/O signaling level of the card bus
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SignalVoltage {
+    V330,
+    V180,
+}
+
+/// Bus timing the card was brought to by the last `init`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Timing {
+    /// Default speed, up to 25MHz for SD and 26MHz for MMC
+    Legacy,
+    /// SD High Speed (SDR25) at 50MHz, or MMC High Speed at 52MHz
+    HighSpeed,
+    /// UHS-I SDR50, 100MHz
+    Sdr50,
+    /// UHS-I SDR104, 208MHz
+    Sdr104,
+    /// UHS-I DDR50, 50MHz on both edges
+    Ddr50,
+    /// eMMC HS200, 200MHz SDR at 1.8V
+    Hs200,
+    /// eMMC HS400, 200MHz DDR on an 8-bit bus at 1.8V
+    Hs400,
+}
+
+impl Timing {
+    /// Sampling point must be found with CMD19/CMD21 before data transfers.
+    /// HS400 keeps the point tuned in HS200 on the way there.
+    pub fn needs_tuning(&self) -> bool {
+        matches!(self, Timing::Sdr50 | Timing::Sdr104 | Timing::Hs200)
+    }
+}
+
+impl From<AccessMode> for Timing {
+    fn from(value: AccessMode) -> Self {
+        match value {
+            AccessMode::Sdr12 => Timing::Legacy,
+            AccessMode::Sdr25 => Timing::HighSpeed,
+            AccessMode::Sdr50 => Timing::Sdr50,
+            AccessMode::Sdr104 => Timing::Sdr104,
+            AccessMode::Ddr50 => Timing::Ddr50,
+        }
+    }
+}
+
+/// CMD19 tuning block for a 4-bit bus, Ref PLSS_v7_10 Table 4-2
+pub const TUNING_BLOCK_4BIT: [u8; 64] = [
+    0xff, 0x0f, 0xff, 0x00, 0xff, 0xcc, 0xc3, 0xcc, 0xc3, 0x3c, 0xcc, 0xff, 0xfe, 0xff, 0xfe, 0xef,
+    0xff, 0xdf, 0xff, 0xdd, 0xff, 0xfb, 0xff, 0xfb, 0xbf, 0xff, 0x7f, 0xff, 0x77, 0xf7, 0xbd, 0xef,
+    0xff, 0xf0, 0xff, 0xf0, 0x0f, 0xfc, 0xcc, 0x3c, 0xcc, 0x33, 0xcc, 0xcf, 0xff, 0xef, 0xff, 0xee,
+    0xff, 0xfd, 0xff, 0xfd, 0xdf, 0xff, 0xbf, 0xff, 0xbb, 0xff, 0xf7, 0xff, 0xf7, 0x7f, 0x7b, 0xde,
+];
+
+/// CMD21 tuning block for an 8-bit bus, Ref JESD84-B51 Table 29
+pub const TUNING_BLOCK_8BIT: [u8; 128] = [
+    0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0xcc, 0xcc, 0xcc, 0x33, 0xcc, 0xcc,
+    0xcc, 0x33, 0x33, 0xcc, 0xcc, 0xcc, 0xff, 0xff, 0xff, 0xee, 0xff, 0xff, 0xff, 0xee, 0xee, 0xff,
+    0xff, 0xff, 0xdd, 0xff, 0xff, 0xff, 0xdd, 0xdd, 0xff, 0xff, 0xff, 0xbb, 0xff, 0xff, 0xff, 0xbb,
+    0xbb, 0xff, 0xff, 0xff, 0x77, 0xff, 0xff, 0xff, 0x77, 0x77, 0xff, 0x77, 0xbb, 0xdd, 0xee, 0xff,
+    0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0xcc, 0xcc, 0xcc, 0x33, 0xcc,
+    0xcc, 0xcc, 0x33, 0x33, 0xcc, 0xcc, 0xcc, 0xff, 0xff, 0xff, 0xee, 0xff, 0xff, 0xff, 0xee, 0xee,
+    0xff, 0xff, 0xff, 0xdd, 0xff, 0xff, 0xff, 0xdd, 0xdd, 0xff, 0xff, 0xff, 0xbb, 0xff, 0xff, 0xff,
+    0xbb, 0xbb, 0xff, 0xff, 0xff, 0x77, 0xff, 0xff, 0xff, 0x77, 0x77, 0xff, 0x77, 0xbb, 0xdd, 0xee,
+];
+
+/// Access modes tried on a 1.8V card, fastest first
+const UHS_MODES: [AccessMode; 4] = [
+    AccessMode::Sdr104,
+    AccessMode::Ddr50,
+    AccessMode::Sdr50,
+    AccessMode::Sdr25,
+];
+
+/// UHS-I bring-up: CMD11 after ACMD41, CMD6 access modes and CMD19/CMD21 tuning
+impl DwMmcHost {
+    /// Return the I/O supply to 3.3V, as it must be before CMD0
+    pub(crate) fn reset_signal_voltage(&mut self) {
+        if self.signal_voltage == SignalVoltage::V330 {
+            return;
+        }
+        if let Some(regulator) = self.config.hooks.regulator {
+            if !regulator(SignalVoltage::V330) {
+                warn!("regulator failed to return to 3.3V");
+            }
+        }
+        self.mmc_opt.set_uhs(UHS_VOLT_18 | UHS_DDR, false);
+        self.signal_voltage = SignalVoltage::V330;
+    }
+
+    /// CMD11 once ACMD41 reported S18A, before CMD2
+    pub(crate) fn sd_voltage_switch(&mut self) -> Result<(), CardError> {
+        let Some(regulator) = self.config.hooks.regulator else {
+            return Ok(());
+        };
+        if !self.ocr.v18_allowed() {
+            return Ok(());
+        }
+        if let Err(err) = self.mmc_opt.voltage_switch(regulator) {
+            // the card now needs a power cycle, leave the supply where the next init expects it
+            self.signal_voltage = SignalVoltage::V180;
+            self.reset_signal_voltage();
+            return Err(err);
+        }
+        self.signal_voltage = SignalVoltage::V180;
+        info!("sd signaling at 1.8V");
+        Ok(())
+    }
+
+    /// Fastest access mode both the card and the board support, switched with CMD6
+    pub(crate) fn sd_uhs_timing(&mut self) -> Result<Timing, CardError> {
+        // 0xF leaves every group as is and only reports what the card supports
+        let check = self
+            .mmc_opt
+            .function_switch(false, SwitchGroup::AccessMode, 0xF)?;
+        for mode in UHS_MODES {
+            if !self.config.uhs_modes.contains(&mode)
+                || !check.is_supported(SwitchGroup::AccessMode, mode as u8)
+            {
+                continue;
+            }
+            if mode == AccessMode::Sdr104 && self.config.hooks.sample_phase.is_none() {
+                debug!("sdr104 needs tuning, no sample phase hook");
+                continue;
+            }
+            let set = self
+                .mmc_opt
+                .function_switch(true, SwitchGroup::AccessMode, mode as u8)?;
+            if !set.switched(SwitchGroup::AccessMode, mode as u8) {
+                continue;
+            }
+            if mode == AccessMode::Ddr50 {
+                self.mmc_opt.set_uhs(UHS_DDR, true);
+            }
+            info!("sd access mode: {:?}", mode);
+            return Ok(mode.into());
+        }
+        Ok(Timing::Legacy)
+    }
+
+    /// Sweep the sample phases through the platform hook and settle in the middle
+    /// of the widest window that reads the tuning block back intact
+    pub(crate) fn execute_tuning(&self) -> Result<(), CardError> {
+        let Some(sample_phase) = self.config.hooks.sample_phase else {
+            // SDR50 runs untuned when the board has no phase control
+            return Ok(());
+        };
+        if self.card_type.is_sd() {
+            self.tune_with(sample_phase, send_tuning_block(), &TUNING_BLOCK_4BIT)
+        } else if self.config.bus_width == BusWidth::Eight {
+            self.tune_with(sample_phase, send_tuning_block_hs200(), &TUNING_BLOCK_8BIT)
+        } else {
+            self.tune_with(sample_phase, send_tuning_block_hs200(), &TUNING_BLOCK_4BIT)
+        }
+    }
+
+    fn tune_with(
+        &self,
+        sample_phase: fn(u16),
+        cmd: Command,
+        pattern: &[u8],
+    ) -> Result<(), CardError> {
+        let phases = self.config.tuning_phases;
+        // best window as (start, length), windows may wrap around the last phase
+        let mut best = (0, 0);
+        let mut first_len = None;
+        let mut start = 0;
+        let mut len = 0;
+        for phase in 0..phases {
+            sample_phase(phase);
+            if self.mmc_opt.tuning_block(cmd, pattern)? {
+                if len == 0 {
+                    start = phase;
+                }
+                len += 1;
+                continue;
+            }
+            if first_len.is_none() {
+                first_len = Some(len);
+            }
+            if len > best.1 {
+                best = (start, len);
+            }
+            len = 0;
+        }
+        match first_len {
+            // every phase passed
+            None => best = (0, len),
+            Some(first) if len + first > best.1 => best = (start, len + first),
+            _ => {}
+        }
+        if best.1 == 0 {
+            return Err(CardError::TuningFailed);
+        }
+        let phase = (best.0 + best.1 / 2) % phases;
+        debug!("tuning window {:?}, sample phase {}", best, phase);
+        sample_phase(phase);
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use core::fmt::Display;
+
+use super::info::CardInfo;
+use super::sd_reg::CardType;
+
+/// SD Association manufacturer IDs
+const SD_VENDORS: [(u8, &str); 13] = [
+    (0x01, "Panasonic"),
+    (0x02, "Toshiba"),
+    (0x03, "SanDisk"),
+    (0x1b, "Samsung"),
+    (0x1d, "ADATA"),
+    (0x27, "Phison"),
+    (0x28, "Lexar"),
+    (0x31, "Silicon Power"),
+    (0x41, "Kingston"),
+    (0x74, "Transcend"),
+    (0x76, "Patriot"),
+    (0x82, "Sony"),
+    (0x9f, "Kingston"),
+];
+
+/// OEM IDs of cards whose manufacturer ID is not listed, e.g. rebranded cards
+const SD_OEMS: [(&[u8; 2], &str); 6] = [
+    (b"SD", "SanDisk"),
+    (b"TM", "Toshiba"),
+    (b"SM", "Samsung"),
+    (b"PA", "Panasonic"),
+    (b"JE", "Transcend"),
+    (b"SO", "Sony"),
+];
+
+/// JEDEC eMMC manufacturer IDs
+const MMC_VENDORS: [(u8, &str); 9] = [
+    (0x02, "SanDisk"),
+    (0x11, "Toshiba"),
+    (0x13, "Micron"),
+    (0x15, "Samsung"),
+    (0x45, "SanDisk"),
+    (0x70, "Kingston"),
+    (0x88, "Foresee"),
+    (0x90, "SK Hynix"),
+    (0xfe, "Micron"),
+];
+
+/// SDXC starts above 32GB
+const SDHC_MAX_BYTES: u64 = 32 << 30;
+
+/// Capacities printed on card labels, in decimal MB
+const LABEL_SIZES_MB: [u64; 23] = [
+    8, 16, 32, 64, 128, 256, 512, 1_000, 2_000, 4_000, 8_000, 16_000, 32_000, 48_000, 64_000,
+    128_000, 200_000, 256_000, 400_000, 512_000, 1_000_000, 1_500_000, 2_000_000,
+];
+
+/// Vendor of an SD card manufacturer ID
+pub fn sd_manufacturer(mid: u8) -> Option<&'static str> {
+    lookup(&SD_VENDORS, mid)
+}
+
+/// Vendor of a JEDEC eMMC manufacturer ID
+pub fn mmc_manufacturer(mid: u8) -> Option<&'static str> {
+    lookup(&MMC_VENDORS, mid)
+}
+
+fn lookup(table: &[(u8, &'static str)], mid: u8) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(id, _)| *id == mid)
+        .map(|(_, name)| *name)
+}
+
+impl CardInfo {
+    /// Vendor name from the manufacturer ID, or from the OEM ID of an SD card
+    pub fn manufacturer(&self) -> Option<&'static str> {
+        if self.card_type() == CardType::Mmc {
+            return mmc_manufacturer(self.manufacturer_id());
+        }
+        sd_manufacturer(self.manufacturer_id()).or_else(|| {
+            let oem = self.oem_id().to_be_bytes();
+            SD_OEMS
+                .iter()
+                .find(|(id, _)| **id == oem)
+                .map(|(_, name)| *name)
+        })
+    }
+
+    /// One line description for logs, e.g. `SanDisk SDHC 32GB, rev 8.0, 2023-05`
+    pub fn description(&self) -> CardDescription<'_> {
+        CardDescription(self)
+    }
+
+    /// Capacity class of the card as printed on its label
+    fn type_label(&self) -> &'static str {
+        match self.card_type() {
+            CardType::SdscV1 | CardType::SdscV2 => "SDSC",
+            CardType::SdhcXc if self.capacity() <= SDHC_MAX_BYTES => "SDHC",
+            CardType::SdhcXc => "SDXC",
+            CardType::Sduc => "SDUC",
+            CardType::Mmc => "eMMC",
+            CardType::Unknown => "card",
+        }
+    }
+}
+
+/// Formats a [`CardInfo`] as vendor, type, size, revision and date
+pub struct CardDescription<'a>(&'a CardInfo);
+
+impl Display for CardDescription<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let info = self.0;
+        match info.manufacturer() {
+            Some(name) => write!(f, "{} ", name)?,
+            None => write!(f, "MID {:#04x} ", info.manufacturer_id())?,
+        }
+        write!(f, "{} ", info.type_label())?;
+        write_size(f, label_mb(info.capacity()))?;
+        let (major, minor) = info.product_revision();
+        let (month, year) = info.manufacturing_date();
+        write!(f, ", rev {}.{}, {}-{:02}", major, minor, year, month)
+    }
+}
+
+/// Label size in MB of a card of `capacity` bytes. A card reports a little less than
+/// its label, e.g. 31.9e9 bytes is 32GB; a size no label is close to is kept as is.
+fn label_mb(capacity: u64) -> u64 {
+    let mb = capacity.div_ceil(1_000_000);
+    LABEL_SIZES_MB
+        .into_iter()
+        .find(|&label| label >= mb)
+        .filter(|&label| mb >= label * 9 / 10)
+        .unwrap_or(mb)
+}
+
+/// `mb` decimal megabytes in the largest unit it reaches, with one decimal if needed
+fn write_size(f: &mut core::fmt::Formatter<'_>, mb: u64) -> core::fmt::Result {
+    let (unit_mb, unit) = match mb {
+        1_000_000.. => (1_000_000, "TB"),
+        1_000.. => (1_000, "GB"),
+        _ => (1, "MB"),
+    };
+    match mb * 10 / unit_mb % 10 {
+        0 => write!(f, "{}{}", mb / unit_mb, unit),
+        tenth => write!(f, "{}.{}{}", mb / unit_mb, tenth, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_sizes() {
+        assert_eq!(label_mb(0), 0);
+        assert_eq!(label_mb(31_914_983_424), 32_000);
+        assert_eq!(label_mb(47_500_000_000), 48_000);
+        assert_eq!(label_mb(199_360_000_000), 200_000);
+        assert_eq!(label_mb(398_500_000_000), 400_000);
+        assert_eq!(label_mb(999_000_000_000), 1_000_000);
+        // nowhere near a label
+        assert_eq!(label_mb(3_000_000_000), 3_000);
+    }
+}
@@ -0,0 +1,128 @@
+//! Device tree probing against the blobs in `fixtures/`, built from the `.dts` next
+//! to them with `dtc -O dtb`, `-V 16` for `mshc-v16.dtb`. `truncated.dtb` is the
+//! first half of `mshc.dtb`.
+#![cfg(feature = "fdt")]
+
+use dw_sd::err::Fdt;
+use dw_sd::{mshc_nodes, BusWidth, CdGpio, MshcNode};
+
+const MSHC: &[u8] = include_bytes!("fixtures/mshc.dtb");
+const MSHC_V16: &[u8] = include_bytes!("fixtures/mshc-v16.dtb");
+const TRUNCATED: &[u8] = include_bytes!("fixtures/truncated.dtb");
+
+fn nodes(blob: &[u8]) -> Vec<MshcNode<'_>> {
+    mshc_nodes(blob)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
+fn node<'a>(nodes: &[MshcNode<'a>], name: &str) -> MshcNode<'a> {
+    *nodes.iter().find(|node| node.name == name).unwrap()
+}
+
+#[test]
+fn enabled_nodes_in_tree_order() {
+    let names: Vec<_> = nodes(MSHC).iter().map(|node| node.name).collect();
+    assert_eq!(
+        names,
+        [
+            "mmc@120000000",
+            "mmc@16010000",
+            "mmc@16020000",
+            "mmc@16050000",
+            "mmc@16060000"
+        ]
+    );
+}
+
+#[test]
+fn disabled_node_is_skipped() {
+    assert!(nodes(MSHC).iter().all(|node| node.name != "mmc@16030000"));
+}
+
+#[test]
+fn node_without_reg_does_not_hide_later_ones() {
+    let nodes = nodes(MSHC);
+    assert!(nodes.iter().all(|node| node.name != "mmc@16040000"));
+    assert_eq!(node(&nodes, "mmc@16050000").base, 0x1605_0000);
+}
+
+#[test]
+fn reg_follows_parent_cells() {
+    let nodes = nodes(MSHC);
+    let root = node(&nodes, "mmc@120000000");
+    assert_eq!((root.base, root.size), (0x1_2000_0000, 0x4000));
+    let soc = node(&nodes, "mmc@16010000");
+    assert_eq!((soc.base, soc.size), (0x1601_0000, 0x1_0000));
+}
+
+#[test]
+fn generic_node_config() {
+    let node = node(&nodes(MSHC), "mmc@16010000");
+    let config = node.config;
+    assert_eq!(config.ciu_clock, 100_000_000);
+    assert_eq!(config.max_clock, 50_000_000);
+    assert_eq!(config.fifo_depth, Some(32));
+    assert_eq!(config.bus_width, BusWidth::Four);
+    assert!(config.high_speed);
+    assert!(node.no_sdio);
+}
+
+#[test]
+fn cd_gpio_flags() {
+    let node = node(&nodes(MSHC), "mmc@16010000");
+    assert_eq!(
+        node.cd_gpio,
+        Some(CdGpio {
+            controller: 1,
+            pin: 41,
+            active_low: true,
+        })
+    );
+    // CDETECT is not wired, the slot waits for a card detect hook
+    assert!(!node.config.removable);
+    assert!(node.config.hooks.card_detect.is_none());
+}
+
+#[test]
+fn card_detect_wiring() {
+    let nodes = nodes(MSHC);
+    assert!(node(&nodes, "mmc@16050000").config.removable);
+    assert!(!node(&nodes, "mmc@16060000").config.removable);
+}
+
+#[test]
+fn vendor_compatible_nodes() {
+    let nodes = nodes(MSHC);
+    let starfive = node(&nodes, "mmc@16020000");
+    assert_eq!(starfive.config.bus_width, BusWidth::Eight);
+    assert!(!starfive.config.removable);
+    // the binding defaults apply without bus-width and cap-sd-highspeed
+    let rockchip = node(&nodes, "mmc@16050000");
+    assert_eq!(rockchip.config.bus_width, BusWidth::One);
+    assert!(!rockchip.config.high_speed);
+    assert!(rockchip.config.cd_active_high);
+    assert!(rockchip.config.wp_active_low);
+    assert_eq!(rockchip.cd_gpio, None);
+}
+
+#[test]
+fn version_16_header() {
+    let nodes = nodes(MSHC_V16);
+    assert_eq!(nodes.len(), 1);
+    assert_eq!((nodes[0].base, nodes[0].size), (0x1_0000, 0x1000));
+}
+
+#[test]
+fn truncated_blob() {
+    assert!(matches!(mshc_nodes(TRUNCATED), Err(Fdt::Truncated)));
+    assert!(matches!(mshc_nodes(&MSHC[..20]), Err(Fdt::Truncated)));
+}
+
+#[test]
+fn bad_magic() {
+    let mut blob = MSHC.to_vec();
+    blob[0] = 0;
+    assert!(matches!(mshc_nodes(&blob), Err(Fdt::BadMagic)));
+}